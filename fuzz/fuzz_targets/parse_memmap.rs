@@ -0,0 +1,26 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rust_roulette::memmap::parse_memmap;
+
+// Whatever --maps-path is given, the parser has to turn it into mappings and per-line errors, or
+// fail cleanly on I/O (which includes text that isn't UTF-8), and never panic. Lines that overlap
+// or come out of order are errors too, counted in `overlaps` since they're how a torn read shows,
+// so the mappings that come out are always sorted and disjoint.
+fuzz_target!(|data: &[u8]| {
+    let Ok(parsed) = parse_memmap(data) else {
+        return;
+    };
+    for mapping in &parsed.mappings {
+        assert!(mapping.start_addr <= mapping.end_addr, "{}", mapping);
+        assert!(mapping.size() == mapping.end_addr - mapping.start_addr);
+    }
+    for pair in parsed.mappings.windows(2) {
+        assert!(pair[0].end_addr <= pair[1].start_addr, "{} then {}", pair[0], pair[1]);
+    }
+    assert!(parsed.overlaps <= parsed.errors.len());
+    // Every line is either a mapping or an error, never both or neither
+    let lines =
+        data.split(|&b| b == b'\n').count() - usize::from(data.is_empty() || data.ends_with(b"\n"));
+    assert_eq!(parsed.mappings.len() + parsed.errors.len(), lines);
+});
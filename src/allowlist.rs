@@ -0,0 +1,163 @@
+use crate::memmap::Mapping;
+use anyhow::{Context, Result, anyhow};
+use std::fs;
+use std::path::Path;
+
+/// An `--allowlist` file: one basename or glob per line, and only mappings that match a line are
+/// played with. A pattern with a `/` in it is matched against the whole path instead of the
+/// basename. Blank lines and lines starting with # are ignored.
+///
+/// ```
+/// use rust_roulette::allowlist::Allowlist;
+/// use rust_roulette::memmap::Mapping;
+///
+/// let mapping = |path: &str| Mapping::new(0x1000, 0x2000, "r--p".into(), 0, "fd:00".into(), 1, path.into());
+/// let allowlist = Allowlist::parse("# libraries\nlibc.so.*\n/usr/bin/*\n")?;
+/// assert!(allowlist.allows(&mapping("/usr/lib/x86_64-linux-gnu/libc.so.6")));
+/// assert!(allowlist.allows(&mapping("/usr/bin/python3")));
+/// assert!(!allowlist.allows(&mapping("/usr/lib/libm.so.6")));
+/// assert!(!allowlist.allows(&mapping("")));
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+pub struct Allowlist {
+    patterns: Vec<String>,
+}
+
+impl Allowlist {
+    pub fn load(path: &Path) -> Result<Allowlist> {
+        let text =
+            fs::read_to_string(path).context(format!("Failed to read {}", path.display()))?;
+        Allowlist::parse(&text).context(format!("Invalid allowlist {}", path.display()))
+    }
+
+    pub fn parse(text: &str) -> Result<Allowlist> {
+        let patterns: Vec<String> = text
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(str::to_string)
+            .collect();
+        if patterns.is_empty() {
+            return Err(anyhow!("no patterns, so nothing would be allowed"));
+        }
+        Ok(Allowlist { patterns })
+    }
+
+    pub fn allows(&self, mapping: &Mapping) -> bool {
+        self.patterns.iter().any(|p| matches(p, mapping))
+    }
+
+    // The patterns that match none of the given mappings, for --strict-allowlist
+    pub fn unmatched(&self, mappings: &[Mapping]) -> Vec<&str> {
+        self.patterns
+            .iter()
+            .filter(|p| !mappings.iter().any(|m| matches(p, m)))
+            .map(String::as_str)
+            .collect()
+    }
+}
+
+fn matches(pattern: &str, mapping: &Mapping) -> bool {
+    // Anonymous mappings have no name to match
+    if mapping.path.is_empty() {
+        return false;
+    }
+    if pattern.contains('/') {
+        glob_match(pattern, &mapping.path)
+    } else {
+        glob_match(pattern, mapping.basename())
+    }
+}
+
+/// Shell-style glob matching: `*` matches any run of characters, `?` any one, and `[...]` any one
+/// of a set, with ranges like `a-z` and `!` or `^` first to negate it.
+///
+/// ```
+/// use rust_roulette::allowlist::glob_match;
+///
+/// assert!(glob_match("lib*.so.[0-9]", "libc.so.6"));
+/// assert!(glob_match("ld-linux-*", "ld-linux-x86-64.so.2"));
+/// assert!(glob_match("?ibc*", "libc.so.6"));
+/// assert!(!glob_match("lib[!c]*", "libc.so.6"));
+/// assert!(!glob_match("libc", "libc.so.6"));
+/// assert!(glob_match("*", ""));
+/// ```
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    // Where to pick up after the last `*` if what follows it stops matching: the pattern just after
+    // the star, and the name one further along than last time
+    let mut backtrack = None;
+    while n < name.len() {
+        match pattern.get(p).copied() {
+            Some('*') => {
+                backtrack = Some((p + 1, n + 1));
+                p += 1;
+                continue;
+            }
+            Some('?') => {
+                p += 1;
+                n += 1;
+                continue;
+            }
+            Some('[') => match match_class(&pattern[p..], name[n]) {
+                Some((true, len)) => {
+                    p += len;
+                    n += 1;
+                    continue;
+                }
+                Some((false, _)) => {}
+                // A bracket that's never closed is just a bracket
+                None if name[n] == '[' => {
+                    p += 1;
+                    n += 1;
+                    continue;
+                }
+                None => {}
+            },
+            Some(c) if c == name[n] => {
+                p += 1;
+                n += 1;
+                continue;
+            }
+            _ => {}
+        }
+        match backtrack {
+            Some((star_p, star_n)) => {
+                p = star_p;
+                n = star_n;
+                backtrack = Some((star_p, star_n + 1));
+            }
+            None => return false,
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+// Match one character against the `[...]` class at the start of `class`, returning whether it
+// matched and how long the class is, or None if it's never closed
+fn match_class(class: &[char], c: char) -> Option<(bool, usize)> {
+    let mut i = 1;
+    let negated = matches!(class.get(i).copied(), Some('!' | '^'));
+    if negated {
+        i += 1;
+    }
+    let mut matched = false;
+    let mut first = true;
+    loop {
+        let &start = class.get(i)?;
+        // A `]` straight after the opening bracket is part of the set rather than its end
+        if start == ']' && !first {
+            return Some((matched != negated, i + 1));
+        }
+        first = false;
+        if class.get(i + 1) == Some(&'-') && class.get(i + 2).is_some_and(|&end| end != ']') {
+            matched |= (start..=class[i + 2]).contains(&c);
+            i += 3;
+        } else {
+            matched |= start == c;
+            i += 1;
+        }
+    }
+}
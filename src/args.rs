@@ -0,0 +1,596 @@
+use anyhow::{Context, Result, anyhow};
+use nix::sys::signal::Signal;
+use rand::Rng;
+use rust_roulette::allowlist::Allowlist;
+use rust_roulette::format::{OutputFormat, ProcessSortKey, SortKey, Units};
+use rust_roulette::heatmap::HeatMap;
+use rust_roulette::memmap::{MappingKind, PermissionSet};
+use rust_roulette::permconfig::PermConfig;
+use std::fmt::Display;
+use std::path::PathBuf;
+use std::process;
+use std::str::FromStr;
+
+pub const USAGE: &str = "\
+Usage: rust_roulette [OPTIONS] [COMMAND]
+
+Commands:
+  resolve <basename> <offset>  Print the virtual address of a file offset (hex) in a mapped file
+  dump <addr> <len>            Hexdump len bytes of the target's memory from addr (hex)
+  diff <file-a> <file-b>       Compare two saved maps files (procfs text or --format json)
+  selftest                     Check that our own live memory map parses sanely (read-only)
+
+Options:
+  --pid <pid>           Play against another process instead of ourselves. Repeat to spread
+                        the shots across several processes
+  --auto                Keep firing without waiting for ENTER
+  --demo                Play by itself at a watchable pace, narrating what it finds and
+                        counting down to the first shot. --quiet drops the narration and pauses
+  --one-shot            Fire a single shot without asking, print it and the summary, and exit
+  --burst <n>           Fire n shots every time ENTER is pressed
+  --rounds <n>          Stop after this many rounds
+  --min-rounds <n>      Aim the first n shots only at scratch pages and anonymous data that
+                        isn't heap, a stack or a file's .bss. That makes dying early less
+                        likely, not impossible
+  --seed <n>            Seed the random number generator, to replay a game exactly
+  --state-file <file>   Save the seed and progress after every round, and resume from the file
+                        if it already exists
+  --timeline <file>     Write a CSV row per round for plotting: round, milliseconds since the
+                        start, mapping, bytes written and the running total
+  --idle-timeout <secs> Stop waiting for ENTER after this long without input
+  --idle-action <act>   What to do on an idle timeout: fire or quit [default: quit]
+  --sweep               Overwrite one random mapping page by page instead of random pages
+  --script <file>       Take the shots from a file instead, one per round. Each line is
+                        'basename offset length fill', with the offset (hex) relative to
+                        where the file is mapped and a hex fill byte
+  --heatmap <file>      Aim more shots at spots that were fatal before, each a line of
+                        'basename offset weight' with the offset in hex from the lowest address
+                        the file is mapped at. A shot anywhere else weighs 1
+  --stick               Keep hitting whichever mapping the first shot picked, at new random
+                        pages, until its process dies or you re-roll it with 'r'
+  --per-mapping-budget <secs>
+                        Walk through every candidate mapping in order, hitting each one at
+                        random pages for this long, or until its process dies, before moving on
+  --edge-pages <which>  Hit the first, last or (randomly) either page of the chosen mapping:
+                        first, last or both. First pages hold ELF headers and the like
+  --only <basename>     Only target mappings of this file, e.g. libc.so.6
+  --allowlist <file>    Only target mappings matching a line of this file, each a basename or a
+                        glob like lib*.so.* (matched against the whole path if it has a '/')
+  --strict-allowlist    Refuse to play if a line of the --allowlist matches none of a target's
+                        mappings
+  --target-tls          Only target small anonymous mappings next to thread stacks, which are
+                        likely thread-local storage. A guess: maps doesn't label TLS
+  --write-size <bytes>  Overwrite this many bytes per shot, starting anywhere in the mapping,
+                        instead of one whole page. A range like 16-4096 picks a new size each
+                        round. Clamped to the size of the mapping
+  --align-writes        Round the start of every write down to a page boundary
+  --round-marker        Start every write with a marker of the round it was, for telling which
+                        round hit what afterwards: the magic 0x52444e52 and the round, both
+                        little-endian u32s
+  --bitflip             Flip one bit of one byte per shot, picked from the seed, instead of
+                        overwriting anything
+  --corruption-ratio <f>
+                        Only ever hit this fraction (0-1] of each mapping's pages, a subset
+                        picked from the seed the first time the mapping is chosen
+  --cooldown <n>        Once a page is hit, re-roll any shot at it for the next n rounds, to
+                        spread the damage around
+  --resident-only       Only hit pages that are in RAM right now, going by /proc/PID/pagemap,
+                        rather than paging in swapped-out or untouched ones. Reading another
+                        process's pagemap needs the same access as --pid does
+  --sandbox <basename>  Copy a mapping of this file into a memfd and only shoot at the copy, shown
+                        as [sandbox:basename]. Purely illustrative: real content gets corrupted,
+                        but nothing in use ever is
+  --add-scratch <n>     Add n harmless scratch pages of our own to the targets
+  --catch-signals <sig,...>
+                        Fatal signals to report before we die of them when playing against
+                        ourselves [default: SIGSEGV,SIGBUS]
+  --mmap-file <file>    Map a file read-only into the game, without making it writeable like the
+                        rest, to see a write hit real memory protection
+  --exclude-heap        Never target the [heap]. Clobbering malloc's bookkeeping tends to end
+                        in hangs and confusing aborts rather than a clean crash
+  --make-perms <perms>  Set our mappings to these permissions before playing instead of rwxp,
+                        e.g. rw-p to keep data non-executable. Code stays executable so we can
+                        keep running, and without w, shots into our own memory fault
+  --perm-config <file>  Set permissions per mapping from a JSON object of patterns to
+                        permissions, e.g. {\"libgcc_s.so.*\": \"r--p\", \"[heap]\": \"rwxp\"}. The
+                        first match wins, and the rest get --make-perms. Taking w from data we
+                        still write to ourselves, like libc's, kills us before the first shot
+  --include-guard-pages Target the inaccessible guard pages below thread stacks too, which are
+                        otherwise left alone
+  --protect-self        Never shoot at our own executable's code, data and .bss, or the vDSO,
+                        so that a game against ourselves lasts longer
+  --protect-argv        Never shoot at the top of our [stack], where argv and the environment
+                        are. Found from /proc/self/stat, and rounded out to whole pages
+  --skip-kinds <kind,...>
+                        Leave out exactly these kinds of mapping: code, data, heap, stack,
+                        anonymous, vdso, vvar, vsyscall or special. Without it, every
+                        [bracketed] region is left out, heap and stack included
+  --no-mprotect         Don't change any permissions, and only target mappings that are already
+                        writeable. Against ourselves that means the heap, data and stack
+  --snapshot-on-crash <file>
+                        When a --pid target dies, save the mapping it was last shot in (as it
+                        looked after that shot) to this file
+  --bundle <dir>        When a --pid target dies, write everything needed to reproduce it to
+                        this directory: the seed, its map and layout fingerprint, every shot it
+                        took as JSON and as a --script, its last shot mapping and a reproduce.sh
+                        to replay the shots
+  --metrics-port <port> Serve the game's counters over HTTP in Prometheus' text format. Needs a
+                        build with the metrics feature
+  --corrupt-all-segments <basename> <offset>
+                        Skip the game and overwrite every segment of this file in each --pid
+                        target at the same hex offset from the segment's start, with a page or
+                        --write-size bytes
+  --corrupt-pc          Skip the game and send every --pid target's instruction pointer to a
+                        random non-executable address with ptrace, for a guaranteed crash.
+                        Needs --yes. x86_64 only
+  --yes                 Confirm --corrupt-pc
+  --quiet               Only print the startup info and the final summary
+  --json-summary        At the end of a game, print the summary to stdout as one JSON object,
+                        with the rounds, bytes written, runtime, seed and pages hit per mapping.
+                        The usual summary goes to stderr instead
+  -v, -vv               Raise the verbosity. At -vv, --list prints each maps line as read above the
+                        fields it was parsed into, instead of the usual listing
+  --time-rounds         Time picking each shot and writing it, in microseconds, and add
+                        min/mean/p99/max of both to the summary
+  --page-size <bytes>   Count pages of this size for --units pages in --list and --perm-stats
+                        instead of the system's, for a --maps-path taken on another system.
+                        Playing always uses the system's
+  --probe               Revoke access to a random mapping of ours and report the first page touched
+  --perms-only          Make our mappings writeable as usual, report how long the mprotect calls
+                        took and which failed, and exit without playing
+  --estimate            Estimate how many rounds the target will survive, without playing
+  --fatality <k=f,...>  Override the chance that a shot into a kind of mapping is fatal for
+                        --estimate, e.g. code=0.9,data=0.1
+  --list                Print the target's memory map and exit
+  --elf-info            Print the ELF program headers of every mapped file next to where each
+                        part of the file is mapped, and exit
+  --visualize           Draw the target's memory map as a colored bar and exit. With NO_COLOR
+                        set it's drawn in letters, and off a terminal it's a table by kind
+  --perm-stats          Print how many mappings of the target have each set of permissions, and
+                        how many bytes they map, and exit
+  --maps-path <file>    Read the map for --list/--visualize/--perm-stats from a saved maps file
+                        or JSON dump. It can be gzipped, or inside a .tar, .tar.gz, .tgz or
+                        .zip of a /proc/PID directory
+  --serve <socket>      Skip the game and answer requests for memory maps on this Unix socket,
+                        for a --remote somewhere else. Read-only: nothing is ever written
+  --remote <socket>     Get the map for --list/--visualize/--perm-stats of --pid from a --serve
+                        on this socket, so the pid is the one the server sees
+  --format <fmt>        Output format for --list: table, json, csv or maps [default: table].
+                        With json, the game also prints a JSON line for every shot
+  --units <unit>        Show sizes in the --list table and the summary in bytes or pages
+                        [default: bytes]
+  --sort-by <key>       Order --list by addr, size (biggest first), perms or path (basename)
+                        [default: addr]
+  --all-processes       Print a line for every process we can read: its name, how many mappings
+                        it has, how much they map and how many are W+X (read-only). --format
+                        can be table, json or csv
+  --sort-processes <key>
+                        Order --all-processes by pid, name, or mappings, size or wx (biggest
+                        first) [default: pid]
+  --watch <pid>         Re-read a process's memory map every second and print changes (read-only)
+  -h, --help            Print this help
+
+Exit status: 0 when the game or command finished normally, 1 if something failed mid-game (or
+selftest found a problem), 2 if it never got started, and 3 if a --pid target died.
+
+Results (listings, dumps, estimates, shot records and the final summary) go to stdout. Everything
+else, from the game's chatter to the prompts, goes to stderr.
+";
+
+pub enum Command {
+    Resolve { basename: String, offset: u64 },
+    Dump { addr: u64, len: u64 },
+    Diff { old: PathBuf, new: PathBuf },
+    Selftest,
+}
+
+#[derive(Clone, Copy, Default, PartialEq)]
+pub enum IdleAction {
+    Fire,
+    #[default]
+    Quit,
+}
+
+impl FromStr for IdleAction {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<IdleAction> {
+        match s {
+            "fire" => Ok(IdleAction::Fire),
+            "quit" => Ok(IdleAction::Quit),
+            _ => Err(anyhow!("expected fire or quit")),
+        }
+    }
+}
+
+// How many bytes a shot overwrites: always the same, or drawn afresh each round from min..=max
+#[derive(Clone, Copy)]
+pub enum WriteSize {
+    Fixed(u64),
+    Range(u64, u64),
+}
+
+impl WriteSize {
+    pub fn draw(&self, rng: &mut impl Rng) -> u64 {
+        match *self {
+            WriteSize::Fixed(size) => size,
+            WriteSize::Range(min, max) => rng.random_range(min..=max),
+        }
+    }
+}
+
+impl FromStr for WriteSize {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<WriteSize> {
+        let size = match s.split_once('-') {
+            Some((min, max)) => {
+                let min: u64 = min.parse().context(format!("Invalid minimum '{}'", min))?;
+                let max: u64 = max.parse().context(format!("Invalid maximum '{}'", max))?;
+                if min > max {
+                    return Err(anyhow!("minimum {} is bigger than maximum {}", min, max));
+                }
+                WriteSize::Range(min, max)
+            }
+            None => WriteSize::Fixed(s.parse().context(format!("Invalid size '{}'", s))?),
+        };
+        if matches!(size, WriteSize::Fixed(0) | WriteSize::Range(0, _)) {
+            return Err(anyhow!("a write must be at least 1 byte"));
+        }
+        Ok(size)
+    }
+}
+
+// Which end of a mapping --edge-pages hits
+#[derive(Clone, Copy, PartialEq)]
+pub enum EdgePages {
+    First,
+    Last,
+    Both,
+}
+
+impl FromStr for EdgePages {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<EdgePages> {
+        match s {
+            "first" => Ok(EdgePages::First),
+            "last" => Ok(EdgePages::Last),
+            "both" => Ok(EdgePages::Both),
+            _ => Err(anyhow!("expected first, last or both")),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Options {
+    pub command: Option<Command>,
+    pub pids: Vec<u32>,
+    pub auto: bool,
+    pub one_shot: bool,
+    pub demo: bool,
+    pub burst: Option<u64>,
+    pub rounds: Option<u64>,
+    pub min_rounds: Option<u64>,
+    pub seed: Option<u64>,
+    pub state_file: Option<PathBuf>,
+    pub timeline: Option<PathBuf>,
+    pub idle_timeout: Option<u64>,
+    pub per_mapping_budget: Option<u64>,
+    pub idle_action: IdleAction,
+    pub sweep: bool,
+    pub script: Option<PathBuf>,
+    pub stick: bool,
+    pub edge_pages: Option<EdgePages>,
+    pub only: Option<String>,
+    pub allowlist: Option<Allowlist>,
+    pub heatmap: Option<HeatMap>,
+    pub strict_allowlist: bool,
+    pub target_tls: bool,
+    pub write_size: Option<WriteSize>,
+    pub align_writes: bool,
+    pub bitflip: bool,
+    pub round_marker: bool,
+    pub corruption_ratio: Option<f64>,
+    pub resident_only: bool,
+    pub cooldown: Option<u64>,
+    pub sandbox: Option<String>,
+    pub add_scratch: Option<u64>,
+    pub catch_signals: Option<Vec<Signal>>,
+    pub mmap_file: Option<PathBuf>,
+    pub exclude_heap: bool,
+    pub skip_kinds: Option<Vec<MappingKind>>,
+    pub no_mprotect: bool,
+    pub include_guard_pages: bool,
+    pub protect_self: bool,
+    pub protect_argv: bool,
+    pub make_perms: Option<PermissionSet>,
+    pub perm_config: Option<PermConfig>,
+    pub snapshot_on_crash: Option<PathBuf>,
+    pub bundle: Option<PathBuf>,
+    pub corrupt_pc: bool,
+    pub corrupt_all_segments: Option<(String, u64)>,
+    pub yes: bool,
+    pub quiet: bool,
+    pub json_summary: bool,
+    pub verbose: u8,
+    pub time_rounds: bool,
+    pub metrics_port: Option<u16>,
+    pub page_size: Option<u64>,
+    pub probe: bool,
+    pub perms_only: bool,
+    pub estimate: bool,
+    pub fatality: Vec<(MappingKind, f64)>,
+    pub list: bool,
+    pub elf_info: bool,
+    pub visualize: bool,
+    pub perm_stats: bool,
+    pub maps_path: Option<PathBuf>,
+    pub serve: Option<PathBuf>,
+    pub remote: Option<PathBuf>,
+    pub format: OutputFormat,
+    pub sort_by: SortKey,
+    pub size_in_pages: bool,
+    pub all_processes: bool,
+    pub sort_processes: ProcessSortKey,
+    pub watch: Option<u32>,
+}
+
+impl Options {
+    pub fn parse<I: Iterator<Item = String>>(mut args: I) -> Result<Options> {
+        let mut opts = Options::default();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--pid" => opts.pids.push(value(&arg, args.next())?),
+                "--auto" => opts.auto = true,
+                // A demo runs itself, just slowly enough to watch
+                "--demo" => {
+                    opts.demo = true;
+                    opts.auto = true;
+                }
+                "--one-shot" => opts.one_shot = true,
+                "--burst" => opts.burst = Some(value(&arg, args.next())?),
+                "--rounds" => opts.rounds = Some(value(&arg, args.next())?),
+                "--min-rounds" => opts.min_rounds = Some(value(&arg, args.next())?),
+                "--seed" => opts.seed = Some(value(&arg, args.next())?),
+                "--state-file" => opts.state_file = Some(value(&arg, args.next())?),
+                "--timeline" => opts.timeline = Some(value(&arg, args.next())?),
+                "--idle-timeout" => opts.idle_timeout = Some(value(&arg, args.next())?),
+                "--per-mapping-budget" => opts.per_mapping_budget = Some(value(&arg, args.next())?),
+                "--idle-action" => opts.idle_action = value(&arg, args.next())?,
+                "--sweep" => opts.sweep = true,
+                "--script" => opts.script = Some(value(&arg, args.next())?),
+                "--stick" => opts.stick = true,
+                "--edge-pages" => opts.edge_pages = Some(value(&arg, args.next())?),
+                "--only" => opts.only = Some(value(&arg, args.next())?),
+                "--allowlist" => {
+                    let path: PathBuf = value(&arg, args.next())?;
+                    opts.allowlist = Some(Allowlist::load(&path)?);
+                }
+                "--strict-allowlist" => opts.strict_allowlist = true,
+                "--heatmap" => {
+                    let path: PathBuf = value(&arg, args.next())?;
+                    opts.heatmap = Some(HeatMap::load(&path)?);
+                }
+                "--target-tls" => opts.target_tls = true,
+                "--write-size" => opts.write_size = Some(value(&arg, args.next())?),
+                "--align-writes" => opts.align_writes = true,
+                "--bitflip" => opts.bitflip = true,
+                "--round-marker" => opts.round_marker = true,
+                "--corruption-ratio" => opts.corruption_ratio = Some(value(&arg, args.next())?),
+                "--resident-only" => opts.resident_only = true,
+                "--cooldown" => opts.cooldown = Some(value(&arg, args.next())?),
+                "--sandbox" => opts.sandbox = Some(value(&arg, args.next())?),
+                "--add-scratch" => opts.add_scratch = Some(value(&arg, args.next())?),
+                "--catch-signals" => opts.catch_signals = Some(signals(&arg, args.next())?),
+                "--mmap-file" => opts.mmap_file = Some(value(&arg, args.next())?),
+                "--exclude-heap" => opts.exclude_heap = true,
+                "--skip-kinds" => opts.skip_kinds = Some(kinds(&arg, args.next())?),
+                "--no-mprotect" => opts.no_mprotect = true,
+                "--include-guard-pages" => opts.include_guard_pages = true,
+                "--protect-self" => opts.protect_self = true,
+                "--protect-argv" => opts.protect_argv = true,
+                "--make-perms" => opts.make_perms = Some(value(&arg, args.next())?),
+                "--perm-config" => {
+                    let path: PathBuf = value(&arg, args.next())?;
+                    opts.perm_config = Some(PermConfig::load(&path)?);
+                }
+                "--snapshot-on-crash" => opts.snapshot_on_crash = Some(value(&arg, args.next())?),
+                "--bundle" => opts.bundle = Some(value(&arg, args.next())?),
+                "--corrupt-pc" => opts.corrupt_pc = true,
+                "--corrupt-all-segments" => {
+                    let basename = value(&arg, args.next())?;
+                    let offset: String = value(&arg, args.next())?;
+                    let hex = offset.strip_prefix("0x").unwrap_or(&offset);
+                    let offset = u64::from_str_radix(hex, 16)
+                        .context(format!("Invalid hex offset '{}' for {}", offset, arg))?;
+                    opts.corrupt_all_segments = Some((basename, offset));
+                }
+                "--yes" => opts.yes = true,
+                "--quiet" => opts.quiet = true,
+                "--json-summary" => opts.json_summary = true,
+                "-v" => opts.verbose += 1,
+                "-vv" => opts.verbose += 2,
+                "--time-rounds" => opts.time_rounds = true,
+                "--metrics-port" => opts.metrics_port = Some(value(&arg, args.next())?),
+                "--page-size" => opts.page_size = Some(value(&arg, args.next())?),
+                "--probe" => opts.probe = true,
+                "--perms-only" => opts.perms_only = true,
+                "--estimate" => opts.estimate = true,
+                "--fatality" => opts.fatality = fatality(&arg, args.next())?,
+                "--list" => opts.list = true,
+                "--elf-info" => opts.elf_info = true,
+                "--visualize" => opts.visualize = true,
+                "--perm-stats" => opts.perm_stats = true,
+                "--maps-path" => opts.maps_path = Some(value(&arg, args.next())?),
+                "--serve" => opts.serve = Some(value(&arg, args.next())?),
+                "--remote" => opts.remote = Some(value(&arg, args.next())?),
+                "--format" => opts.format = value(&arg, args.next())?,
+                "--sort-by" => opts.sort_by = value(&arg, args.next())?,
+                "--units" => {
+                    opts.size_in_pages = match value::<String>(&arg, args.next())?.as_str() {
+                        "bytes" => false,
+                        "pages" => true,
+                        other => {
+                            return Err(anyhow!(
+                                "Invalid value '{}' for --units: expected bytes or pages",
+                                other
+                            ));
+                        }
+                    }
+                }
+                "--all-processes" => opts.all_processes = true,
+                "--sort-processes" => opts.sort_processes = value(&arg, args.next())?,
+                "--watch" => opts.watch = Some(value(&arg, args.next())?),
+                "resolve" if opts.command.is_none() => {
+                    opts.command = Some(Command::Resolve {
+                        basename: value(&arg, args.next())?,
+                        offset: hex(&arg, args.next())?,
+                    })
+                }
+                "dump" if opts.command.is_none() => {
+                    opts.command = Some(Command::Dump {
+                        addr: hex(&arg, args.next())?,
+                        len: value(&arg, args.next())?,
+                    })
+                }
+                "diff" if opts.command.is_none() => {
+                    opts.command = Some(Command::Diff {
+                        old: value(&arg, args.next())?,
+                        new: value(&arg, args.next())?,
+                    })
+                }
+                "selftest" if opts.command.is_none() => opts.command = Some(Command::Selftest),
+                "-h" | "--help" => {
+                    print!("{}", USAGE);
+                    process::exit(0);
+                }
+                _ => return Err(anyhow!("Unknown argument '{}'\n\n{}", arg, USAGE)),
+            }
+        }
+        // A single shot is just a one-round game that doesn't wait for ENTER
+        if opts.one_shot {
+            if opts.rounds.is_some_and(|rounds| rounds != 1) {
+                return Err(anyhow!(
+                    "--one-shot fires exactly one round, so it can't be used with --rounds"
+                ));
+            }
+            opts.rounds = Some(1);
+            opts.auto = true;
+        }
+        Ok(opts)
+    }
+
+    // The units sizes are shown to people in
+    pub fn units(&self, pagesize: u64) -> Units {
+        if self.size_in_pages {
+            Units::Pages(pagesize)
+        } else {
+            Units::Bytes
+        }
+    }
+
+    // The process that single-target commands work on: the --pid if there's one, else ourselves
+    pub fn single_target(&self) -> Result<u32> {
+        match self.pids.as_slice() {
+            [] => Ok(process::id()),
+            [pid] => Ok(*pid),
+            _ => Err(anyhow!("Only one --pid can be given here")),
+        }
+    }
+}
+
+// Parse the value following a flag, naming the flag in the error if it's missing or malformed
+fn value<T>(flag: &str, value: Option<String>) -> Result<T>
+where
+    T: FromStr,
+    T::Err: Display + Send + Sync + 'static,
+{
+    let value = value.ok_or(anyhow!("{} requires a value", flag))?;
+    value
+        .parse::<T>()
+        .map_err(|e| anyhow!("{}", e))
+        .context(format!("Invalid value '{}' for {}", value, flag))
+}
+
+// Like `value`, but for hex numbers with an optional 0x prefix
+fn hex(flag: &str, value: Option<String>) -> Result<u64> {
+    let value = value.ok_or(anyhow!("{} requires a value", flag))?;
+    let digits = value.strip_prefix("0x").unwrap_or(&value);
+    u64::from_str_radix(digits, 16).context(format!("Invalid hex value '{}' for {}", value, flag))
+}
+
+// Parse a comma separated list of signal names, with or without the SIG prefix
+fn signals(flag: &str, value: Option<String>) -> Result<Vec<Signal>> {
+    let value = value.ok_or(anyhow!("{} requires a value", flag))?;
+    value
+        .split(',')
+        .map(|name| {
+            let name = name.trim().to_uppercase();
+            let name = if name.starts_with("SIG") {
+                name
+            } else {
+                format!("SIG{}", name)
+            };
+            Signal::from_str(&name).map_err(|_| anyhow!("unknown signal '{}'", name))
+        })
+        .collect::<Result<_>>()
+        .context(format!("Invalid value '{}' for {}", value, flag))
+}
+
+// Parse a comma separated list of mapping kinds
+fn kinds(flag: &str, value: Option<String>) -> Result<Vec<MappingKind>> {
+    let value = value.ok_or(anyhow!("{} requires a value", flag))?;
+    value
+        .split(',')
+        .map(|kind| kind.trim().parse())
+        .collect::<Result<_>>()
+        .context(format!("Invalid value '{}' for {}", value, flag))
+}
+
+// Parse a comma separated list of kind=fraction pairs
+fn fatality(flag: &str, value: Option<String>) -> Result<Vec<(MappingKind, f64)>> {
+    let value = value.ok_or(anyhow!("{} requires a value", flag))?;
+    value
+        .split(',')
+        .map(|pair| {
+            let (kind, fraction) = pair
+                .split_once('=')
+                .ok_or(anyhow!("expected kind=fraction, got '{}'", pair))?;
+            let fraction: f64 = fraction
+                .parse()
+                .context(format!("Invalid fraction '{}'", fraction))?;
+            if !(0.0..=1.0).contains(&fraction) {
+                return Err(anyhow!("fraction {} isn't between 0 and 1", fraction));
+            }
+            Ok((kind.parse()?, fraction))
+        })
+        .collect::<Result<_>>()
+        .context(format!("Invalid value '{}' for {}", value, flag))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    #[test]
+    fn write_sizes_parse_and_draw_within_their_bounds() {
+        assert!(matches!("64".parse(), Ok(WriteSize::Fixed(64))));
+        assert!(matches!("1-4096".parse(), Ok(WriteSize::Range(1, 4096))));
+        assert!(matches!("8-8".parse(), Ok(WriteSize::Range(8, 8))));
+        for bad in [
+            "0", "0-16", "16-8", "", "-", "1-", "-1", "a-b", "4k", "1-2-3",
+        ] {
+            assert!(bad.parse::<WriteSize>().is_err(), "{}", bad);
+        }
+
+        let mut rng = StdRng::seed_from_u64(5);
+        assert_eq!(WriteSize::Fixed(64).draw(&mut rng), 64);
+        let range = WriteSize::Range(10, 13);
+        let drawn: std::collections::HashSet<u64> =
+            (0..200).map(|_| range.draw(&mut rng)).collect();
+        assert_eq!(drawn, (10..=13).collect());
+    }
+}
@@ -0,0 +1,172 @@
+use anyhow::{Context, Result};
+use rust_roulette::format::{OutputFormat, Units, format_mappings};
+use rust_roulette::memmap::{Mapping, layout_fingerprint};
+use std::env;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+
+// A shot as --bundle keeps it: the same JSON record --format json prints, and the line of a --script
+// that fires it again, or a comment saying why no script can
+pub struct LoggedShot {
+    pub record: String,
+    pub script_line: String,
+}
+
+// The --script line for a shot, with the offset taken from the lowest address the file is mapped
+// at, which is what the script resolves it against
+pub fn script_line(
+    maps: &[Mapping],
+    mapping: &Mapping,
+    addr: u64,
+    len: u64,
+    fill: u8,
+    bit: Option<u8>,
+) -> String {
+    let base = maps
+        .iter()
+        .filter(|m| !mapping.path.is_empty() && m.basename() == mapping.basename())
+        .map(|m| m.start_addr)
+        .min();
+    match (base, bit) {
+        (_, Some(bit)) => format!("# 0x{:x} bit {}: a script can't flip a bit", addr, bit),
+        (None, _) => format!("# 0x{:x}: a script can't name an anonymous mapping", addr),
+        (Some(base), None) => format!(
+            "{} 0x{:x} {} 0x{:02x}",
+            mapping.basename(),
+            addr - base,
+            len,
+            fill
+        ),
+    }
+}
+
+// Everything needed to reproduce the death of `pid` somewhere else: the seed, every shot it took
+// both as JSON and as a --script, its map from the start of the game and that map's layout
+// fingerprint, the snapshot of its last shot mapping if there is one, and a script that replays
+// the shots against a fresh process. The fingerprint is what groups bundles from the same program.
+pub fn write_bundle(
+    dir: &Path,
+    pid: u32,
+    seed: u64,
+    maps: &[Mapping],
+    shots: &[LoggedShot],
+    snapshot: Option<Vec<u8>>,
+) -> Result<()> {
+    fs::create_dir_all(dir).context(format!("Failed to create {}", dir.display()))?;
+    let write = |name: &str, contents: &[u8]| {
+        let path = dir.join(name);
+        fs::write(&path, contents).context(format!("Failed to write {}", path.display()))
+    };
+    write("seed", format!("{}\n", seed).as_bytes())?;
+    write(
+        "maps",
+        format_mappings(maps, OutputFormat::Maps, Units::Bytes).as_bytes(),
+    )?;
+    write(
+        "fingerprint",
+        format!("{:016x}\n", layout_fingerprint(maps)).as_bytes(),
+    )?;
+    let records: String = shots.iter().map(|s| format!("{}\n", s.record)).collect();
+    write("shots.jsonl", records.as_bytes())?;
+    let script: String = shots
+        .iter()
+        .map(|s| format!("{}\n", s.script_line))
+        .collect();
+    write(
+        "shots.script",
+        format!(
+            "# The shots that killed process {}, in order\n{}",
+            pid, script
+        )
+        .as_bytes(),
+    )?;
+    if let Some(snapshot) = snapshot {
+        write("snapshot", &snapshot)?;
+    }
+
+    let exe = env::current_exe().context("Failed to find our own executable")?;
+    let reproduce = format!(
+        "#!/bin/sh\n\
+         # Fire the shots that killed process {} at the process given as $1. The seed it was\n\
+         # played with is in seed, for re-running the whole game with --seed instead.\n\
+         exec {} --pid \"$1\" --auto --script \"$(dirname \"$0\")/shots.script\"\n",
+        pid,
+        shell_quote(&exe.to_string_lossy())
+    );
+    write("reproduce.sh", reproduce.as_bytes())?;
+    let path = dir.join("reproduce.sh");
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o755))
+        .context(format!("Failed to make {} executable", path.display()))
+}
+
+fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_bundle_has_every_file() {
+        let maps = vec![
+            Mapping::new(
+                0x5555_0000,
+                0x5555_2000,
+                "r-xp".into(),
+                0,
+                "fd:01".into(),
+                7,
+                "/usr/bin/victim".into(),
+            ),
+            Mapping::new(
+                0x7f00_0000,
+                0x7f00_4000,
+                "rw-p".into(),
+                0,
+                "00:00".into(),
+                0,
+                "".into(),
+            ),
+        ];
+        let shots = vec![
+            LoggedShot {
+                record: "{\"round\": 1}".into(),
+                script_line: script_line(&maps, &maps[0], 0x5555_1000, 64, 0xff, None),
+            },
+            LoggedShot {
+                record: "{\"round\": 2}".into(),
+                script_line: script_line(&maps, &maps[1], 0x7f00_2000, 4096, 0xff, None),
+            },
+        ];
+        let dir = env::temp_dir().join(format!("rust_roulette-test-{}-bundle", std::process::id()));
+        write_bundle(&dir, 4321, 99, &maps, &shots, Some(vec![0xaa; 16])).unwrap();
+        let read = |name: &str| fs::read_to_string(dir.join(name)).unwrap();
+
+        assert_eq!(read("seed"), "99\n");
+        assert_eq!(
+            read("maps"),
+            format_mappings(&maps, OutputFormat::Maps, Units::Bytes)
+        );
+        assert_eq!(
+            read("fingerprint"),
+            format!("{:016x}\n", layout_fingerprint(&maps))
+        );
+        assert_eq!(read("shots.jsonl"), "{\"round\": 1}\n{\"round\": 2}\n");
+        assert_eq!(
+            read("shots.script"),
+            "# The shots that killed process 4321, in order\n\
+             victim 0x1000 64 0xff\n\
+             # 0x7f002000: a script can't name an anonymous mapping\n"
+        );
+        assert_eq!(fs::read(dir.join("snapshot")).unwrap(), [0xaa; 16]);
+        let reproduce = dir.join("reproduce.sh");
+        assert!(read("reproduce.sh").starts_with("#!/bin/sh\n"));
+        assert_eq!(
+            fs::metadata(&reproduce).unwrap().permissions().mode() & 0o777,
+            0o755
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
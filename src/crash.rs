@@ -0,0 +1,178 @@
+use anyhow::{Context, Result};
+use nix::libc;
+use nix::sys::signal::{SaFlags, SigAction, SigHandler, SigSet, Signal, sigaction};
+use std::ffi::c_void;
+use std::sync::atomic::{AtomicPtr, AtomicU64, AtomicUsize, Ordering};
+
+// The range being probed and the page size, read from inside the signal handler. Atomics are the
+// only shared state we can safely touch from there.
+static PROBE_START: AtomicU64 = AtomicU64::new(0);
+static PROBE_END: AtomicU64 = AtomicU64::new(0);
+static PAGE_SIZE: AtomicU64 = AtomicU64::new(4096);
+
+// The read-only --mmap-file mapping and its path, for the violation handler
+static WATCH_START: AtomicU64 = AtomicU64::new(0);
+static WATCH_END: AtomicU64 = AtomicU64::new(0);
+static WATCH_NAME: AtomicPtr<u8> = AtomicPtr::new(std::ptr::null_mut());
+static WATCH_NAME_LEN: AtomicUsize = AtomicUsize::new(0);
+
+// si_code for a fault on a page that's mapped but doesn't allow the access. The libc crate doesn't
+// export the SIGSEGV codes.
+const SEGV_ACCERR: libc::c_int = 2;
+
+// Report the first access to [start, end) and exit. This has to be installed before the range's
+// permissions are revoked.
+pub fn install_probe_handler(start: u64, end: u64, pagesize: u64) -> Result<()> {
+    PROBE_START.store(start, Ordering::SeqCst);
+    PROBE_END.store(end, Ordering::SeqCst);
+    PAGE_SIZE.store(pagesize, Ordering::SeqCst);
+
+    let action = SigAction::new(
+        SigHandler::SigAction(probe_handler),
+        SaFlags::SA_SIGINFO,
+        SigSet::empty(),
+    );
+    unsafe { sigaction(Signal::SIGSEGV, &action) }.context("Failed to install SIGSEGV handler")?;
+    Ok(())
+}
+
+// Everything in here must be async-signal-safe: no allocation, no locks, no println! If the probed
+// mapping is one the handler itself needs (e.g. the TLS block write() puts errno in), the report
+// gets cut short when the handler faults too.
+extern "C" fn probe_handler(_: libc::c_int, info: *mut libc::siginfo_t, _: *mut c_void) {
+    let addr = unsafe { (*info).si_addr() } as u64;
+    let start = PROBE_START.load(Ordering::SeqCst);
+    let end = PROBE_END.load(Ordering::SeqCst);
+    let pagesize = PAGE_SIZE.load(Ordering::SeqCst);
+
+    if addr >= start && addr < end {
+        let page = addr & !(pagesize - 1);
+        write_stderr(b"\nProbe hit! First touch at ");
+        write_hex(addr);
+        write_stderr(b" in page ");
+        write_hex(page);
+        write_stderr(b" (page index ");
+        write_hex((page - start) / pagesize);
+        write_stderr(b")\n");
+    } else {
+        write_stderr(b"\nSegfault at ");
+        write_hex(addr);
+        write_stderr(b", outside of the probed mapping\n");
+    }
+    unsafe { libc::_exit(1) };
+}
+
+// Report any of `signals` with its name and, for faults, the address, then die of it as usual
+pub fn install_crash_handlers(signals: &[Signal]) -> Result<()> {
+    let action = SigAction::new(
+        SigHandler::SigAction(crash_handler),
+        SaFlags::SA_SIGINFO | SaFlags::SA_RESETHAND,
+        SigSet::empty(),
+    );
+    for &signal in signals {
+        unsafe { sigaction(signal, &action) }
+            .context(format!("Failed to install {} handler", signal))?;
+    }
+    Ok(())
+}
+
+// The default action is back in place by now. The signal is blocked until we return, so raising
+// it again kills us as soon as we do, even for signals that returning would otherwise swallow.
+// This runs inside the process whose memory the game has been trashing, so it doesn't always get
+// as far as printing anything.
+extern "C" fn crash_handler(signum: libc::c_int, info: *mut libc::siginfo_t, _: *mut c_void) {
+    write_stderr(b"\nCaught ");
+    match Signal::try_from(signum) {
+        Ok(signal) => write_stderr(signal.as_str().as_bytes()),
+        Err(_) => write_hex(signum as u64),
+    }
+    // si_addr is only meaningful for the signals raised by a faulting instruction
+    if matches!(
+        signum,
+        libc::SIGSEGV | libc::SIGBUS | libc::SIGILL | libc::SIGFPE
+    ) {
+        write_stderr(b" at ");
+        write_hex(unsafe { (*info).si_addr() } as u64);
+    }
+    write_stderr(b"\n");
+    unsafe { libc::raise(signum) };
+}
+
+// Explain a fault in [start, end) as a write to a read-only mapping called `name`, then let the
+// process die of the SIGSEGV as usual
+pub fn install_violation_handler(start: u64, end: u64, name: &str) -> Result<()> {
+    // The handler can't allocate, so the name has to outlive everything
+    let name: &'static mut [u8] = Box::leak(name.as_bytes().to_vec().into_boxed_slice());
+    WATCH_START.store(start, Ordering::SeqCst);
+    WATCH_END.store(end, Ordering::SeqCst);
+    WATCH_NAME_LEN.store(name.len(), Ordering::SeqCst);
+    WATCH_NAME.store(name.as_mut_ptr(), Ordering::SeqCst);
+
+    let action = SigAction::new(
+        SigHandler::SigAction(violation_handler),
+        SaFlags::SA_SIGINFO | SaFlags::SA_RESETHAND,
+        SigSet::empty(),
+    );
+    unsafe { sigaction(Signal::SIGSEGV, &action) }.context("Failed to install SIGSEGV handler")?;
+    Ok(())
+}
+
+// SA_RESETHAND has already put the default action back by the time this runs, so returning
+// re-runs the faulting write and the process dies with a real SIGSEGV
+extern "C" fn violation_handler(_: libc::c_int, info: *mut libc::siginfo_t, _: *mut c_void) {
+    let addr = unsafe { (*info).si_addr() } as u64;
+    let code = unsafe { (*info).si_code };
+    let start = WATCH_START.load(Ordering::SeqCst);
+    let end = WATCH_END.load(Ordering::SeqCst);
+    let name = unsafe {
+        std::slice::from_raw_parts(
+            WATCH_NAME.load(Ordering::SeqCst),
+            WATCH_NAME_LEN.load(Ordering::SeqCst),
+        )
+    };
+
+    if addr >= start && addr < end {
+        write_stderr(b"\nPermission violation at ");
+        write_hex(addr);
+        write_stderr(b" in ");
+        write_stderr(name);
+        if code == SEGV_ACCERR {
+            write_stderr(b": the page is mapped, but read-only\n");
+        } else {
+            write_stderr(b"\n");
+        }
+    } else {
+        write_stderr(b"\nSegfault at ");
+        write_hex(addr);
+        write_stderr(b", outside of ");
+        write_stderr(name);
+        write_stderr(b"\n");
+    }
+}
+
+fn write_stderr(msg: &[u8]) {
+    unsafe {
+        libc::write(
+            libc::STDERR_FILENO,
+            msg.as_ptr() as *const c_void,
+            msg.len(),
+        );
+    }
+}
+
+fn write_hex(value: u64) {
+    let mut buf = [0u8; 18];
+    buf[0] = b'0';
+    buf[1] = b'x';
+    let mut len = 2;
+    let mut started = false;
+    for shift in (0..16).rev() {
+        let nibble = ((value >> (shift * 4)) & 0xf) as u8;
+        if nibble != 0 || started || shift == 0 {
+            started = true;
+            buf[len] = b"0123456789abcdef"[nibble as usize];
+            len += 1;
+        }
+    }
+    write_stderr(&buf[..len]);
+}
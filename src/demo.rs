@@ -0,0 +1,54 @@
+use std::thread;
+use std::time::Duration;
+
+use crate::game::TargetProcess;
+
+// How long --demo lets each line sink in, and how long it waits between shots
+const BEAT: Duration = Duration::from_millis(1200);
+pub const SHOT_PAUSE: Duration = Duration::from_millis(1500);
+
+// The guided tour that --demo gives before the first shot: what we found, the welcome, the quote,
+// and a countdown. With --quiet none of this is shown, and there's nothing to wait for either.
+pub fn introduce(targets: &[TargetProcess]) {
+    eprintln!("Let's see what we've got to play with...\n");
+    thread::sleep(BEAT);
+    for target in targets {
+        // Each file once, at the lowest address it's mapped at
+        let mut files: Vec<(&str, u64)> = Vec::new();
+        let mut anonymous = 0;
+        for mapping in &target.candidates {
+            if mapping.path.is_empty() {
+                anonymous += 1;
+                continue;
+            }
+            match files
+                .iter_mut()
+                .find(|(name, _)| *name == mapping.basename())
+            {
+                Some((_, start)) => *start = (*start).min(mapping.start_addr),
+                None => files.push((mapping.basename(), mapping.start_addr)),
+            }
+        }
+        for (name, start) in files {
+            eprintln!("Found {} at 0x{:X}", name, start);
+            thread::sleep(BEAT / 4);
+        }
+        eprintln!(
+            "...and {} anonymous mapping(s), {} in all\n",
+            anonymous,
+            target.candidates.len()
+        );
+        thread::sleep(BEAT);
+    }
+
+    eprintln!("Welcome to Rust Roulette! It's a daring game where pages of memory are overwritten");
+    eprintln!("until something terrible happens!\n");
+    thread::sleep(BEAT * 2);
+    eprintln!("'Some of you may die, but it's a sacrifice I'm willing to make' - J. Lithgow\n");
+    thread::sleep(BEAT * 2);
+    for n in (1..=3).rev() {
+        eprintln!("{}...", n);
+        thread::sleep(BEAT);
+    }
+    eprintln!();
+}
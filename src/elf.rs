@@ -0,0 +1,175 @@
+use anyhow::{Context, Result, anyhow};
+use std::fs;
+use std::path::Path;
+
+// Just enough ELF to read the program headers for --elf-info. That's a handful of fixed-offset
+// fields, so a crate like `object` or `goblin`, which parse the whole file however much of it is
+// wanted, would be far more than this needs. Every offset comes from the file, so all the
+// arithmetic on them is checked and every read is bounds-checked.
+
+// One entry of an ELF file's program header table, which is what the loader goes by when it maps
+// the file. Only 64-bit little-endian files are read, since that's all we run on.
+pub struct ProgramHeader {
+    pub p_type: u32,
+    pub p_flags: u32,
+    pub p_offset: u64,
+    pub p_vaddr: u64,
+    pub p_filesz: u64,
+    pub p_memsz: u64,
+}
+
+pub const PT_LOAD: u32 = 1;
+
+const PF_X: u32 = 1;
+const PF_W: u32 = 2;
+const PF_R: u32 = 4;
+
+impl ProgramHeader {
+    pub fn type_name(&self) -> String {
+        match self.p_type {
+            0 => "NULL".to_string(),
+            PT_LOAD => "LOAD".to_string(),
+            2 => "DYNAMIC".to_string(),
+            3 => "INTERP".to_string(),
+            4 => "NOTE".to_string(),
+            5 => "SHLIB".to_string(),
+            6 => "PHDR".to_string(),
+            7 => "TLS".to_string(),
+            0x6474e550 => "GNU_EH_FRAME".to_string(),
+            0x6474e551 => "GNU_STACK".to_string(),
+            0x6474e552 => "GNU_RELRO".to_string(),
+            0x6474e553 => "GNU_PROPERTY".to_string(),
+            other => format!("0x{:x}", other),
+        }
+    }
+
+    // The flags in the same rwx form as maps uses
+    pub fn flags(&self) -> String {
+        [(PF_R, 'r'), (PF_W, 'w'), (PF_X, 'x')]
+            .iter()
+            .map(|&(bit, c)| if self.p_flags & bit != 0 { c } else { '-' })
+            .collect()
+    }
+}
+
+pub fn read_program_headers(path: &Path) -> Result<Vec<ProgramHeader>> {
+    let bytes = fs::read(path).context(format!("Failed to read {}", path.display()))?;
+    parse_program_headers(&bytes).context(format!("Failed to parse ELF file {}", path.display()))
+}
+
+pub fn parse_program_headers(bytes: &[u8]) -> Result<Vec<ProgramHeader>> {
+    if bytes.len() < 64 || bytes[..4] != *b"\x7fELF" {
+        return Err(anyhow!("not an ELF file"));
+    }
+    // EI_CLASS and EI_DATA
+    if bytes[4] != 2 || bytes[5] != 1 {
+        return Err(anyhow!("only 64-bit little-endian ELF files are supported"));
+    }
+    let u16_at = |at: usize| u16::from_le_bytes([bytes[at], bytes[at + 1]]);
+    let phoff = u64_at(bytes, 0x20)?;
+    let phentsize = u16_at(0x36) as u64;
+    let phnum = u16_at(0x38) as u64;
+    if phentsize < 56 {
+        return Err(anyhow!("program headers are only {} bytes each", phentsize));
+    }
+
+    (0..phnum)
+        .map(|i| {
+            let at = i
+                .checked_mul(phentsize)
+                .and_then(|offset| offset.checked_add(phoff))
+                .and_then(|at| usize::try_from(at).ok())
+                .ok_or(anyhow!(
+                    "program header {} is out of range at offset 0x{:x}",
+                    i,
+                    phoff
+                ))?;
+            Ok(ProgramHeader {
+                p_type: u32_at(bytes, at)?,
+                p_flags: u32_at(bytes, at.saturating_add(4))?,
+                p_offset: u64_at(bytes, at.saturating_add(8))?,
+                p_vaddr: u64_at(bytes, at.saturating_add(16))?,
+                p_filesz: u64_at(bytes, at.saturating_add(32))?,
+                p_memsz: u64_at(bytes, at.saturating_add(40))?,
+            })
+        })
+        .collect()
+}
+
+fn field<const N: usize>(bytes: &[u8], at: usize) -> Result<[u8; N]> {
+    at.checked_add(N)
+        .and_then(|end| bytes.get(at..end))
+        .map(|field| field.try_into().unwrap())
+        .ok_or(anyhow!("truncated at byte {}", at))
+}
+
+fn u32_at(bytes: &[u8], at: usize) -> Result<u32> {
+    Ok(u32::from_le_bytes(field(bytes, at)?))
+}
+
+fn u64_at(bytes: &[u8], at: usize) -> Result<u64> {
+    Ok(u64::from_le_bytes(field(bytes, at)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A 64-byte ELF header whose program header table is `phnum` entries at `phoff`
+    fn header(phoff: u64, phnum: u16) -> Vec<u8> {
+        let mut bytes = vec![0; 64];
+        bytes[..4].copy_from_slice(b"\x7fELF");
+        bytes[4] = 2;
+        bytes[5] = 1;
+        bytes[0x20..0x28].copy_from_slice(&phoff.to_le_bytes());
+        bytes[0x36..0x38].copy_from_slice(&56u16.to_le_bytes());
+        bytes[0x38..0x3a].copy_from_slice(&phnum.to_le_bytes());
+        bytes
+    }
+
+    fn load_segment() -> Vec<u8> {
+        let mut entry = vec![0; 56];
+        entry[..4].copy_from_slice(&PT_LOAD.to_le_bytes());
+        entry[4..8].copy_from_slice(&(PF_R | PF_X).to_le_bytes());
+        entry[8..16].copy_from_slice(&0x1000u64.to_le_bytes());
+        entry[16..24].copy_from_slice(&0x401000u64.to_le_bytes());
+        entry[32..40].copy_from_slice(&0x2345u64.to_le_bytes());
+        entry[40..48].copy_from_slice(&0x3000u64.to_le_bytes());
+        entry
+    }
+
+    #[test]
+    fn a_load_segment_is_read_from_its_header() {
+        let mut bytes = header(64, 1);
+        bytes.extend(load_segment());
+        let headers = parse_program_headers(&bytes).unwrap();
+        assert_eq!(headers.len(), 1);
+        let load = &headers[0];
+        assert_eq!(
+            (load.type_name().as_str(), load.flags().as_str()),
+            ("LOAD", "r-x")
+        );
+        assert_eq!(
+            (load.p_offset, load.p_vaddr, load.p_filesz, load.p_memsz),
+            (0x1000, 0x401000, 0x2345, 0x3000)
+        );
+    }
+
+    #[test]
+    fn bad_offsets_are_errors_rather_than_panics() {
+        // Cut off halfway through the only program header
+        let mut bytes = header(64, 1);
+        bytes.extend(&load_segment()[..30]);
+        assert!(parse_program_headers(&bytes).is_err());
+        // A table that runs past the end of the address space
+        for phoff in [u64::MAX, u64::MAX - 60, u64::MAX - 56 * 3] {
+            let mut bytes = header(phoff, 0xffff);
+            bytes.extend(load_segment());
+            assert!(parse_program_headers(&bytes).is_err(), "0x{:x}", phoff);
+        }
+        assert!(parse_program_headers(b"\x7fELF").is_err());
+        let mut big_endian = header(64, 0);
+        big_endian[5] = 2;
+        assert!(parse_program_headers(&big_endian).is_err());
+    }
+}
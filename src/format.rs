@@ -0,0 +1,891 @@
+use crate::json::{self, Value};
+use crate::memmap::{Mapping, ParsedMemmap};
+use anyhow::{Result, anyhow};
+use std::fmt::Write;
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum OutputFormat {
+    #[default]
+    Table,
+    Json,
+    Csv,
+    Maps,
+}
+
+impl FromStr for OutputFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<OutputFormat> {
+        match s {
+            "table" => Ok(OutputFormat::Table),
+            "json" => Ok(OutputFormat::Json),
+            "csv" => Ok(OutputFormat::Csv),
+            "maps" => Ok(OutputFormat::Maps),
+            _ => Err(anyhow!("expected one of table, json, csv or maps")),
+        }
+    }
+}
+
+// How --list orders the mappings
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum SortKey {
+    #[default]
+    Addr,
+    Size,
+    Perms,
+    Path,
+}
+
+impl FromStr for SortKey {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<SortKey> {
+        match s {
+            "addr" => Ok(SortKey::Addr),
+            "size" => Ok(SortKey::Size),
+            "perms" => Ok(SortKey::Perms),
+            "path" => Ok(SortKey::Path),
+            _ => Err(anyhow!("expected one of addr, size, perms or path")),
+        }
+    }
+}
+
+/// Sort mappings for listing: by start address, biggest first, by permissions as maps spells them,
+/// or by basename. Ties keep address order.
+///
+/// ```
+/// use rust_roulette::format::{SortKey, sort_mappings};
+/// use rust_roulette::memmap::Mapping;
+///
+/// let mapping = |start, end, perms: &str, path: &str| {
+///     Mapping::new(start, end, perms.into(), 0, "fd:00".into(), 1, path.into())
+/// };
+/// let mut mappings = vec![
+///     mapping(0x3000, 0x4000, "rw-p", "/lib/b.so"),
+///     mapping(0x1000, 0x3000, "r-xp", "/lib/c.so"),
+///     mapping(0x4000, 0x7000, "r--p", "/lib/a.so"),
+/// ];
+/// let starts = |mappings: &[Mapping]| mappings.iter().map(|m| m.start_addr).collect::<Vec<_>>();
+/// sort_mappings(&mut mappings, SortKey::Addr);
+/// assert_eq!(starts(&mappings), [0x1000, 0x3000, 0x4000]);
+/// sort_mappings(&mut mappings, SortKey::Size);
+/// assert_eq!(starts(&mappings), [0x4000, 0x1000, 0x3000]);
+/// sort_mappings(&mut mappings, SortKey::Perms);
+/// assert_eq!(starts(&mappings), [0x4000, 0x1000, 0x3000]);
+/// sort_mappings(&mut mappings, SortKey::Path);
+/// assert_eq!(starts(&mappings), [0x4000, 0x3000, 0x1000]);
+/// ```
+pub fn sort_mappings(mappings: &mut [Mapping], key: SortKey) {
+    mappings.sort_by_key(|m| m.start_addr);
+    match key {
+        SortKey::Addr => {}
+        SortKey::Size => mappings.sort_by_key(|m| std::cmp::Reverse(m.size())),
+        SortKey::Perms => mappings.sort_by_key(|m| m.permissions.to_string()),
+        SortKey::Path => mappings.sort_by(|a, b| a.basename().cmp(b.basename())),
+    }
+}
+
+// One line of the --all-processes table
+pub struct ProcessSummary {
+    pub pid: u32,
+    pub name: String,
+    pub mappings: usize,
+    pub mapped: u64,
+    pub wx: usize,
+}
+
+impl ProcessSummary {
+    pub fn new(pid: u32, name: String, mappings: &[Mapping]) -> ProcessSummary {
+        ProcessSummary {
+            pid,
+            name,
+            mappings: mappings.len(),
+            mapped: mappings.iter().map(Mapping::size).sum(),
+            wx: mappings.iter().filter(|m| m.is_wx()).count(),
+        }
+    }
+}
+
+// How --all-processes orders its table
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ProcessSortKey {
+    #[default]
+    Pid,
+    Name,
+    Mappings,
+    Size,
+    Wx,
+}
+
+impl FromStr for ProcessSortKey {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<ProcessSortKey> {
+        match s {
+            "pid" => Ok(ProcessSortKey::Pid),
+            "name" => Ok(ProcessSortKey::Name),
+            "mappings" => Ok(ProcessSortKey::Mappings),
+            "size" => Ok(ProcessSortKey::Size),
+            "wx" => Ok(ProcessSortKey::Wx),
+            _ => Err(anyhow!("expected one of pid, name, mappings, size or wx")),
+        }
+    }
+}
+
+/// Sort process summaries by pid, by name, or by mapping count, mapped size or W+X count, biggest
+/// first. Ties keep pid order.
+///
+/// ```
+/// use rust_roulette::format::{ProcessSortKey, ProcessSummary, sort_processes};
+/// use rust_roulette::memmap::Mapping;
+///
+/// let mapping = |end, perms: &str| Mapping::new(0x1000, end, perms.into(), 0, "00:00".into(), 0, "".into());
+/// let mut processes = vec![
+///     ProcessSummary::new(30, "sshd".into(), &[mapping(0xf000, "r-xp")]),
+///     ProcessSummary::new(10, "java".into(), &[mapping(0x9000, "rwxp"), mapping(0x2000, "rw-p")]),
+///     ProcessSummary::new(20, "bash".into(), &[mapping(0x5000, "rw-p")]),
+/// ];
+/// assert_eq!(processes[1].mapped, 0x9000);
+/// assert_eq!(processes[1].wx, 1);
+/// let pids = |processes: &[ProcessSummary]| processes.iter().map(|p| p.pid).collect::<Vec<_>>();
+/// sort_processes(&mut processes, ProcessSortKey::Pid);
+/// assert_eq!(pids(&processes), [10, 20, 30]);
+/// sort_processes(&mut processes, ProcessSortKey::Name);
+/// assert_eq!(pids(&processes), [20, 10, 30]);
+/// sort_processes(&mut processes, ProcessSortKey::Mappings);
+/// assert_eq!(pids(&processes), [10, 20, 30]);
+/// sort_processes(&mut processes, ProcessSortKey::Size);
+/// assert_eq!(pids(&processes), [30, 10, 20]);
+/// sort_processes(&mut processes, ProcessSortKey::Wx);
+/// assert_eq!(pids(&processes), [10, 20, 30]);
+/// ```
+pub fn sort_processes(processes: &mut [ProcessSummary], key: ProcessSortKey) {
+    processes.sort_by_key(|p| p.pid);
+    match key {
+        ProcessSortKey::Pid => {}
+        ProcessSortKey::Name => processes.sort_by(|a, b| a.name.cmp(&b.name)),
+        ProcessSortKey::Mappings => processes.sort_by_key(|p| std::cmp::Reverse(p.mappings)),
+        ProcessSortKey::Size => processes.sort_by_key(|p| std::cmp::Reverse(p.mapped)),
+        ProcessSortKey::Wx => processes.sort_by_key(|p| std::cmp::Reverse(p.wx)),
+    }
+}
+
+// Render the --all-processes table. There's no line-per-mapping form of it, so Maps isn't
+// accepted here.
+pub fn format_processes(processes: &[ProcessSummary], fmt: OutputFormat) -> Result<String> {
+    let mut out = String::new();
+    match fmt {
+        OutputFormat::Table => {
+            let _ = writeln!(
+                out,
+                "{:>8}  {:<16} {:>8} {:>14} {:>5}",
+                "PID", "NAME", "MAPPINGS", "MAPPED", "W+X"
+            );
+            for p in processes {
+                let _ = writeln!(
+                    out,
+                    "{:>8}  {:<16} {:>8} {:>14} {:>5}",
+                    p.pid, p.name, p.mappings, p.mapped, p.wx
+                );
+            }
+        }
+        OutputFormat::Json => {
+            out.push('[');
+            for (i, p) in processes.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                let _ = write!(
+                    out,
+                    "\n  {{\"pid\": {}, \"name\": {}, \"mappings\": {}, \"mapped\": {}, \"wx\": {}}}",
+                    p.pid,
+                    json_string(&p.name),
+                    p.mappings,
+                    p.mapped,
+                    p.wx
+                );
+            }
+            out.push_str("\n]\n");
+        }
+        OutputFormat::Csv => {
+            out.push_str("pid,name,mappings,mapped,wx\n");
+            for p in processes {
+                let _ = writeln!(
+                    out,
+                    "{},{},{},{},{}",
+                    p.pid,
+                    csv_field(&p.name),
+                    p.mappings,
+                    p.mapped,
+                    p.wx
+                );
+            }
+        }
+        OutputFormat::Maps => {
+            return Err(anyhow!(
+                "--all-processes prints a table of processes, which has no maps format"
+            ));
+        }
+    }
+    Ok(out)
+}
+
+// What sizes are shown to people in: bytes, or pages of the given size
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Units {
+    #[default]
+    Bytes,
+    Pages(u64),
+}
+
+impl Units {
+    // `bytes` in these units. Not everything is a whole number of pages, and whatever's left over
+    // shows as a fraction, or as less than the smallest one shown rather than none at all.
+    pub fn amount(self, bytes: u64) -> String {
+        match self {
+            Units::Bytes => bytes.to_string(),
+            Units::Pages(pagesize) if bytes.is_multiple_of(pagesize) => {
+                (bytes / pagesize).to_string()
+            }
+            Units::Pages(pagesize) if bytes * 100 < pagesize => "<0.01".to_string(),
+            Units::Pages(pagesize) => format!("{:.2}", bytes as f64 / pagesize as f64),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Units::Bytes => "bytes",
+            Units::Pages(_) => "pages",
+        }
+    }
+}
+
+/// Render a list of mappings for output. Every format ends with a newline, unless there's nothing
+/// to print. Only the table is meant for people, so it's the only one whose sizes follow `units`.
+///
+/// ```
+/// use rust_roulette::format::{OutputFormat, Units, format_mappings};
+/// use rust_roulette::memmap::Mapping;
+///
+/// let mappings = [Mapping::new(0x1000, 0xb000, "rw-p".into(), 0, "00:00".into(), 0, "[heap]".into())];
+/// let table = format_mappings(&mappings, OutputFormat::Table, Units::Pages(0x1000));
+/// let mut lines = table.lines();
+/// assert!(lines.next().unwrap().contains("PAGES"));
+/// assert_eq!(lines.next().unwrap().split_whitespace().nth(4), Some("10"));
+/// let table = format_mappings(&mappings, OutputFormat::Table, Units::Bytes);
+/// assert_eq!(table.lines().nth(1).unwrap().split_whitespace().nth(4), Some("40960"));
+/// ```
+pub fn format_mappings(mappings: &[Mapping], fmt: OutputFormat, units: Units) -> String {
+    let mut out = String::new();
+    match fmt {
+        OutputFormat::Table => {
+            let _ = writeln!(
+                out,
+                "{:<18} {:<18} {:<5} {:<10} {:>12}  PATH",
+                "START",
+                "END",
+                "PERMS",
+                "OFFSET",
+                match units {
+                    Units::Bytes => "SIZE",
+                    Units::Pages(_) => "PAGES",
+                }
+            );
+            for m in mappings {
+                let _ = writeln!(
+                    out,
+                    "{:<18} {:<18} {:<5} {:<10} {:>12}  {}{}",
+                    format!("0x{:x}", m.start_addr),
+                    format!("0x{:x}", m.end_addr),
+                    m.permissions,
+                    format!("0x{:x}", m.offset),
+                    units.amount(m.size()),
+                    m.path,
+                    // Writeable code is worth pointing out
+                    if m.is_wx() { " [W+X]" } else { "" }
+                );
+            }
+        }
+        OutputFormat::Json => {
+            out.push('[');
+            for (i, m) in mappings.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                let _ = write!(
+                    out,
+                    "\n  {{\"start_addr\": {}, \"end_addr\": {}, \"permissions\": \"{}\", \
+                     \"offset\": {}, \"device\": {}, \"inode\": {}, \"path\": {}}}",
+                    m.start_addr,
+                    m.end_addr,
+                    m.permissions,
+                    m.offset,
+                    json_string(&m.device),
+                    m.inode,
+                    json_string(&m.path)
+                );
+            }
+            out.push_str("\n]\n");
+        }
+        OutputFormat::Csv => {
+            out.push_str("start_addr,end_addr,permissions,offset,device,inode,size,path\n");
+            for m in mappings {
+                let _ = writeln!(
+                    out,
+                    "0x{:x},0x{:x},{},0x{:x},{},{},{},{}",
+                    m.start_addr,
+                    m.end_addr,
+                    m.permissions,
+                    m.offset,
+                    m.device,
+                    m.inode,
+                    m.size(),
+                    csv_field(&m.path)
+                );
+            }
+        }
+        OutputFormat::Maps => {
+            for m in mappings {
+                let line = format!(
+                    "{:08x}-{:08x} {} {:08x} {} {}",
+                    m.start_addr, m.end_addr, m.permissions, m.offset, m.device, m.inode
+                );
+                if m.path.is_empty() {
+                    let _ = writeln!(out, "{} ", line);
+                } else {
+                    // The kernel pads the path out to a fixed column
+                    let _ = writeln!(out, "{:<72} {}", line, m.path);
+                }
+            }
+        }
+    }
+    out
+}
+
+/// How many mappings there are of each set of permissions, and how many bytes they map between
+/// them, biggest total first
+///
+/// ```
+/// use rust_roulette::format::perm_stats;
+/// use rust_roulette::memmap::Mapping;
+///
+/// let mapping = |start, end, perms: &str| Mapping::new(start, end, perms.into(), 0, "00:00".into(), 0, "".into());
+/// let mappings = [
+///     mapping(0x1000, 0x2000, "r--p"),
+///     mapping(0x2000, 0x5000, "r-xp"),
+///     mapping(0x5000, 0x6000, "r--p"),
+///     mapping(0x6000, 0x8000, "rw-p"),
+///     mapping(0x8000, 0x9000, "rwxp"),
+/// ];
+/// let stats = perm_stats(&mappings);
+/// let stats: Vec<(&str, usize, u64)> = stats.iter().map(|(p, n, b)| (p.as_str(), *n, *b)).collect();
+/// assert_eq!(
+///     stats,
+///     [("r-xp", 1, 0x3000), ("r--p", 2, 0x2000), ("rw-p", 1, 0x2000), ("rwxp", 1, 0x1000)]
+/// );
+/// ```
+pub fn perm_stats(mappings: &[Mapping]) -> Vec<(String, usize, u64)> {
+    let mut stats: Vec<(String, usize, u64)> = Vec::new();
+    for m in mappings {
+        let perms = m.permissions.to_string();
+        match stats.iter_mut().find(|(p, _, _)| *p == perms) {
+            Some((_, count, bytes)) => {
+                *count += 1;
+                *bytes += m.size();
+            }
+            None => stats.push((perms, 1, m.size())),
+        }
+    }
+    stats.sort_by(|a, b| b.2.cmp(&a.2).then_with(|| a.0.cmp(&b.0)));
+    stats
+}
+
+// Each mapping's line from the maps file above every field it was parsed into, for spotting where
+// the parser and the kernel disagree. The path is quoted so stray whitespace shows.
+pub fn format_raw_vs_parsed(mappings: &[Mapping]) -> String {
+    let mut out = String::new();
+    for m in mappings {
+        let _ = writeln!(out, "raw:    {}", m.raw_line().unwrap_or("(not kept)"));
+        let _ = writeln!(
+            out,
+            "parsed: start=0x{:x} end=0x{:x} perms={} offset=0x{:x} device={} inode={} path='{}'",
+            m.start_addr, m.end_addr, m.permissions, m.offset, m.device, m.inode, m.path
+        );
+    }
+    out
+}
+
+// Read back a list written with OutputFormat::Json. An entry missing a field fails the whole
+// read, but the list is put in address order and an entry that ends below where it starts or
+// overlaps the one before is dropped and reported in `errors`, as parse_memmap does with a line.
+pub fn parse_mappings_json(text: &str) -> Result<ParsedMemmap> {
+    let value = json::parse(text)?;
+    let items = value
+        .as_array()
+        .ok_or(anyhow!("expected an array of mappings"))?;
+    let mut entries = items
+        .iter()
+        .enumerate()
+        .map(|(i, item)| {
+            let number = |key: &str| {
+                item.get(key).and_then(Value::as_u64).ok_or(anyhow!(
+                    "mapping {} has no numeric '{}'",
+                    i,
+                    key
+                ))
+            };
+            let string = |key: &str| {
+                item.get(key)
+                    .and_then(Value::as_str)
+                    .map(str::to_string)
+                    .ok_or(anyhow!("mapping {} has no string '{}'", i, key))
+            };
+            let mapping = Mapping::new(
+                number("start_addr")?,
+                number("end_addr")?,
+                string("permissions")?,
+                number("offset")?,
+                string("device")?,
+                number("inode")?,
+                string("path")?,
+            );
+            Ok((i, mapping))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    entries.sort_by_key(|(_, mapping)| mapping.start_addr);
+
+    let mut parsed = ParsedMemmap::new();
+    for (i, mapping) in entries {
+        if mapping.end_addr < mapping.start_addr {
+            parsed.errors.push(format!(
+                "mapping {}: 0x{:x}-0x{:x} ends below where it starts",
+                i, mapping.start_addr, mapping.end_addr
+            ));
+        } else {
+            parsed.push(mapping, &format!("mapping {}", i));
+        }
+    }
+    Ok(parsed)
+}
+
+// One round of the game as a line of JSON, so a consumer can follow the shots as they happen
+pub fn shot_record(
+    round: u64,
+    pid: u32,
+    addr: u64,
+    len: u64,
+    bit: Option<u8>,
+    marked: bool,
+    mapping: &Mapping,
+) -> String {
+    // Only --bitflip shots have a bit, and only --round-marker ones a marker, and the fields are
+    // left out of the rest
+    let bit = bit.map_or(String::new(), |bit| format!(", \"bit\": {}", bit));
+    let marked = if marked { ", \"marked\": true" } else { "" };
+    format!(
+        "{{\"round\": {}, \"pid\": {}, \"addr\": {}, \"len\": {}{}{}, \"permissions\": \"{}\", \
+         \"path\": {}}}",
+        round,
+        pid,
+        addr,
+        len,
+        bit,
+        marked,
+        mapping.permissions,
+        json_string(&mapping.path)
+    )
+}
+
+pub const TIMELINE_HEADER: &str = "round,elapsed_ms,mapping,bytes,total_bytes";
+
+// One round of the game as a row of the --timeline CSV
+pub fn timeline_row(
+    round: u64,
+    elapsed_ms: u128,
+    mapping: &Mapping,
+    bytes: u64,
+    total: u64,
+) -> String {
+    format!(
+        "{},{},{},{},{}",
+        round,
+        elapsed_ms,
+        csv_field(mapping.basename()),
+        bytes,
+        total
+    )
+}
+
+// The end-of-game summary, for --json-summary
+pub struct RunSummary {
+    pub rounds: u64,
+    pub bytes_written: u64,
+    pub runtime_secs: f64,
+    pub seed: u64,
+    pub hits: Vec<MappingHits>,
+}
+
+// How much of one mapping was hit over a game
+pub struct MappingHits {
+    pub pid: u32,
+    pub start_addr: u64,
+    pub path: String,
+    pub pages_hit: u64,
+    pub pages: u64,
+}
+
+impl RunSummary {
+    // A single line, so that it can follow the shot records of --format json on the same stream
+    pub fn to_json(&self) -> String {
+        let hits: Vec<String> = self
+            .hits
+            .iter()
+            .map(|h| {
+                format!(
+                    "{{\"pid\": {}, \"start_addr\": {}, \"path\": {}, \"pages_hit\": {}, \
+                     \"pages\": {}}}",
+                    h.pid,
+                    h.start_addr,
+                    json_string(&h.path),
+                    h.pages_hit,
+                    h.pages
+                )
+            })
+            .collect();
+        format!(
+            "{{\"rounds\": {}, \"bytes_written\": {}, \"runtime_secs\": {:.3}, \"seed\": {}, \
+             \"hits\": [{}]}}",
+            self.rounds,
+            self.bytes_written,
+            self.runtime_secs,
+            self.seed,
+            hits.join(", ")
+        )
+    }
+}
+
+// Render bytes like `xxd`: 16 per line in groups of two, with an ASCII gutter. Lines are labelled
+// with the address of their first byte rather than an offset.
+pub fn hexdump(addr: u64, bytes: &[u8]) -> String {
+    let mut out = String::new();
+    for (i, line) in bytes.chunks(16).enumerate() {
+        let mut hex = String::new();
+        for pair in line.chunks(2) {
+            hex.push(' ');
+            for byte in pair {
+                let _ = write!(hex, "{:02x}", byte);
+            }
+        }
+        let ascii: String = line
+            .iter()
+            .map(|&b| {
+                if b.is_ascii_graphic() || b == b' ' {
+                    b as char
+                } else {
+                    '.'
+                }
+            })
+            .collect();
+        // A short last line is padded so that its gutter still lines up
+        let _ = writeln!(out, "{:016x}:{:<40}  {}", addr + i as u64 * 16, hex, ascii);
+    }
+    out
+}
+
+pub(crate) fn json_string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memmap::parse_memmap;
+
+    fn mappings() -> Vec<Mapping> {
+        vec![
+            Mapping::new(
+                0x55d0_0000,
+                0x55d0_2000,
+                "r-xp".into(),
+                0x1000,
+                "fd:01".into(),
+                4242,
+                "/usr/bin/two  spaces, one comma".into(),
+            ),
+            Mapping::new(
+                0x7f00_0000,
+                0x7f00_1000,
+                "rwxp".into(),
+                0,
+                "00:00".into(),
+                0,
+                "".into(),
+            ),
+            Mapping::new(
+                0x7ffc_0000,
+                0x7ffc_3000,
+                "rw-p".into(),
+                0,
+                "00:00".into(),
+                0,
+                "[stack]".into(),
+            ),
+        ]
+    }
+
+    fn fields(m: &Mapping) -> (u64, u64, String, u64, String, u64, String) {
+        (
+            m.start_addr,
+            m.end_addr,
+            m.permissions.to_string(),
+            m.offset,
+            m.device.clone(),
+            m.inode,
+            m.path.clone(),
+        )
+    }
+
+    #[test]
+    fn table_has_a_header_and_a_row_per_mapping() {
+        let table = format_mappings(&mappings(), OutputFormat::Table, Units::Bytes);
+        let lines: Vec<&str> = table.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert!(lines[0].starts_with("START") && lines[0].ends_with("PATH"));
+        assert!(
+            lines[1].starts_with("0x55d00000 ") && lines[1].contains(" 8192  /usr/bin/two  spaces")
+        );
+        // Only the writeable code is pointed out
+        assert!(lines[2].ends_with(" [W+X]"));
+        assert!(!lines[1].contains("[W+X]") && !lines[3].contains("[W+X]"));
+    }
+
+    #[test]
+    fn json_reads_back_as_the_same_mappings() {
+        let json = format_mappings(&mappings(), OutputFormat::Json, Units::Bytes);
+        let parsed = parse_mappings_json(&json).unwrap();
+        assert!(parsed.errors.is_empty());
+        assert_eq!(
+            parsed.mappings.iter().map(fields).collect::<Vec<_>>(),
+            mappings().iter().map(fields).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn json_out_of_order_is_sorted_and_bad_entries_are_dropped() {
+        let entry = |start: u64, end: u64, path: &str| {
+            format!(
+                r#"{{"start_addr": {}, "end_addr": {}, "permissions": "rw-p", "offset": 0, "device": "00:00", "inode": 0, "path": "{}"}}"#,
+                start, end, path
+            )
+        };
+        let json = format!(
+            "[{}]",
+            [
+                entry(0x5000, 0x6000, "c"),
+                entry(0x1000, 0x2000, "a"),
+                entry(0x4000, 0x3000, "reversed"),
+                entry(0x1800, 0x2800, "overlapping"),
+                entry(0x2000, 0x3000, "b"),
+            ]
+            .join(", ")
+        );
+        let parsed = parse_mappings_json(&json).unwrap();
+        let paths: Vec<&str> = parsed.mappings.iter().map(|m| m.path.as_str()).collect();
+        assert_eq!(paths, ["a", "b", "c"]);
+        assert_eq!(parsed.overlaps, 1);
+        assert_eq!(
+            parsed.errors,
+            [
+                "mapping 3: 0x1800-0x2800 starts before the mapping above it ends, at 0x2000",
+                "mapping 2: 0x4000-0x3000 ends below where it starts",
+            ]
+        );
+    }
+
+    #[test]
+    fn csv_has_a_header_and_quotes_awkward_paths() {
+        let csv = format_mappings(&mappings(), OutputFormat::Csv, Units::Bytes);
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(
+            lines,
+            [
+                "start_addr,end_addr,permissions,offset,device,inode,size,path",
+                "0x55d00000,0x55d02000,r-xp,0x1000,fd:01,4242,8192,\"/usr/bin/two  spaces, one comma\"",
+                "0x7f000000,0x7f001000,rwxp,0x0,00:00,0,4096,",
+                "0x7ffc0000,0x7ffc3000,rw-p,0x0,00:00,0,12288,[stack]",
+            ]
+        );
+    }
+
+    #[test]
+    fn maps_reparses_as_the_same_mappings() {
+        let maps = format_mappings(&mappings(), OutputFormat::Maps, Units::Bytes);
+        assert!(
+            maps.lines()
+                .nth(1)
+                .unwrap()
+                .starts_with("7f000000-7f001000 rwxp 00000000 00:00 0")
+        );
+        let parsed = parse_memmap(maps.as_bytes()).unwrap();
+        assert!(parsed.errors.is_empty());
+        assert_eq!(
+            parsed.mappings.iter().map(fields).collect::<Vec<_>>(),
+            mappings().iter().map(fields).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn every_format_of_nothing_is_empty_but_for_headers() {
+        assert_eq!(format_mappings(&[], OutputFormat::Maps, Units::Bytes), "");
+        assert_eq!(
+            format_mappings(&[], OutputFormat::Json, Units::Bytes),
+            "[\n]\n"
+        );
+        assert_eq!(
+            format_mappings(&[], OutputFormat::Csv, Units::Bytes)
+                .lines()
+                .count(),
+            1
+        );
+        assert_eq!(
+            format_mappings(&[], OutputFormat::Table, Units::Bytes)
+                .lines()
+                .count(),
+            1
+        );
+    }
+
+    #[test]
+    fn json_strings_escape_quotes_backslashes_and_control_characters() {
+        assert_eq!(json_string(r#"a"b\c"#), r#""a\"b\\c""#);
+        assert_eq!(
+            json_string("\n\t\r\u{0}\u{1f}"),
+            r#""\n\t\u000d\u0000\u001f""#
+        );
+        // DEL and everything past ASCII are fine as they are
+        assert_eq!(json_string("\u{7f}é🦀"), "\"\u{7f}é🦀\"");
+    }
+
+    #[test]
+    fn json_strings_read_back_unchanged() {
+        for s in [
+            "",
+            "plain",
+            "\"quoted\"",
+            "back\\slash",
+            "\u{0}\u{8}\u{c}\r\n\t",
+            "/tmp/köln 🦀 (deleted)",
+        ] {
+            assert_eq!(
+                json::parse(&json_string(s)).unwrap(),
+                Value::String(s.into())
+            );
+        }
+    }
+
+    #[test]
+    fn run_summaries_are_one_line_of_valid_json() {
+        let awkward = "/tmp/\"quoted\"\\dir/new\nline\u{1}.so";
+        let summary = RunSummary {
+            rounds: 12,
+            bytes_written: 49152,
+            runtime_secs: 1.23456,
+            seed: u64::MAX,
+            hits: vec![
+                MappingHits {
+                    pid: 4321,
+                    start_addr: 0x7f00_0000_0000,
+                    path: awkward.into(),
+                    pages_hit: 3,
+                    pages: 16,
+                },
+                MappingHits {
+                    pid: 4321,
+                    start_addr: 0x1000,
+                    path: "".into(),
+                    pages_hit: 1,
+                    pages: 1,
+                },
+            ],
+        };
+        let line = summary.to_json();
+        assert!(!line.contains('\n'));
+        let value = json::parse(&line).unwrap();
+        let field = |key| value.get(key).unwrap();
+        assert_eq!(field("rounds").as_u64(), Some(12));
+        assert_eq!(field("bytes_written").as_u64(), Some(49152));
+        assert_eq!(field("seed").as_u64(), Some(u64::MAX));
+        assert!(matches!(field("runtime_secs"), json::Value::Number(n) if n == "1.235"));
+        let hits = field("hits").as_array().unwrap();
+        assert_eq!(hits.len(), 2);
+        assert_eq!(hits[0].get("path").unwrap().as_str(), Some(awkward));
+        assert_eq!(
+            hits[0].get("start_addr").unwrap().as_u64(),
+            Some(0x7f00_0000_0000)
+        );
+        assert_eq!(hits[0].get("pages_hit").unwrap().as_u64(), Some(3));
+        assert_eq!(hits[1].get("path").unwrap().as_str(), Some(""));
+
+        let empty = RunSummary {
+            rounds: 0,
+            bytes_written: 0,
+            runtime_secs: 0.0,
+            seed: 1,
+            hits: Vec::new(),
+        };
+        let value = json::parse(&empty.to_json()).unwrap();
+        assert_eq!(
+            value.get("hits").unwrap().as_array().map(|hits| hits.len()),
+            Some(0)
+        );
+    }
+
+    #[test]
+    fn timeline_rows_line_up_with_the_header() {
+        let maps = mappings();
+        let rows: Vec<String> = maps
+            .iter()
+            .enumerate()
+            .map(|(i, m)| timeline_row(i as u64, 250 * i as u128, m, 4096, 4096 * (i as u64 + 1)))
+            .collect();
+        assert_eq!(
+            rows,
+            [
+                "0,0,\"two  spaces, one comma\",4096,4096",
+                "1,250,,4096,8192",
+                "2,500,[stack],4096,12288",
+            ]
+        );
+        assert_eq!(TIMELINE_HEADER.split(',').count(), 5);
+        // Quotes in a name are doubled inside a quoted field
+        let mut quoted = maps[0].clone();
+        quoted.path = "/lib/say \"hi\".so".into();
+        assert_eq!(
+            timeline_row(7, 1, &quoted, 1, 2),
+            "7,1,\"say \"\"hi\"\".so\",1,2"
+        );
+    }
+}
@@ -0,0 +1,1409 @@
+use anyhow::{Context, Result, anyhow};
+use rand::prelude::*;
+use rand::seq::index;
+use rust_roulette::format::{
+    MappingHits, OutputFormat, RunSummary, TIMELINE_HEADER, Units, shot_record, timeline_row,
+};
+use rust_roulette::heatmap::{HotSpot, pick_hot};
+use rust_roulette::memmap::*;
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{self, IsTerminal, Write};
+use std::path::PathBuf;
+use std::process;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::args::{EdgePages, IdleAction, Options};
+use crate::bundle::{LoggedShot, script_line, write_bundle};
+use crate::demo;
+use crate::interrupt;
+use crate::rng::{GameState, SeededRng, save_state};
+use crate::script::ScriptedShot;
+use crate::target::{is_alive, read_target, write_target};
+
+pub const SCRATCH: &str = "[scratch]";
+
+// A process being shot at, along with the mappings that are fair game in it
+pub struct TargetProcess {
+    // None when we're playing against ourselves
+    pub pid: Option<u32>,
+    pub candidates: Vec<Mapping>,
+    // Indices of the candidates that --min-rounds sticks to at first
+    pub safe: Vec<usize>,
+    // Where --heatmap's spots are in this target
+    pub hot_spots: Vec<HotSpot>,
+    pub alive: bool,
+    snapshot: Option<Snapshot>,
+    // For --bundle: the whole map as the game started, and every shot fired so far
+    pub maps: Vec<Mapping>,
+    shot_log: Vec<LoggedShot>,
+    // How much of the process looked like malloc heap when the game started
+    heap_regions: usize,
+    heap_bytes: u64,
+}
+
+// The mapping a target was last shot in, read back right after the shot. If the target turns out
+// to have died, this is our best guess at what killed it.
+struct Snapshot {
+    mapping: Mapping,
+    shot_addr: u64,
+    shot_len: usize,
+    bytes: Vec<u8>,
+}
+
+impl TargetProcess {
+    pub fn new(pid: Option<u32>, candidates: Vec<Mapping>, heap: &[&Mapping]) -> TargetProcess {
+        TargetProcess {
+            pid,
+            candidates,
+            safe: Vec::new(),
+            hot_spots: Vec::new(),
+            alive: true,
+            snapshot: None,
+            maps: Vec::new(),
+            shot_log: Vec::new(),
+            heap_regions: heap.len(),
+            heap_bytes: heap.iter().map(|m| m.size()).sum(),
+        }
+    }
+
+    fn name(&self) -> String {
+        match self.pid {
+            Some(pid) => format!("Process {}", pid),
+            None => "Ourselves".to_string(),
+        }
+    }
+
+    // Check whether the process has died since we last looked. We can't outlive ourselves, so
+    // there's nothing to check in that case.
+    fn check_alive(&mut self) -> bool {
+        if let Some(pid) = self.pid
+            && self.alive
+            && !is_alive(pid)
+        {
+            self.alive = false;
+        }
+        self.alive
+    }
+}
+
+// Latencies for --time-rounds, bucketed by powers of two of microseconds so that the percentiles
+// come without keeping every sample. Bucket i holds the values below 2^i that didn't fit lower down.
+struct Histogram {
+    buckets: [u64; 65],
+    count: u64,
+    total_us: u64,
+    min_us: u64,
+    max_us: u64,
+}
+
+impl Histogram {
+    fn new() -> Histogram {
+        Histogram {
+            buckets: [0; 65],
+            count: 0,
+            total_us: 0,
+            min_us: u64::MAX,
+            max_us: 0,
+        }
+    }
+
+    fn record(&mut self, elapsed: Duration) -> u64 {
+        let us = elapsed.as_micros() as u64;
+        self.buckets[(u64::BITS - us.leading_zeros()) as usize] += 1;
+        self.count += 1;
+        self.total_us += us;
+        self.min_us = self.min_us.min(us);
+        self.max_us = self.max_us.max(us);
+        us
+    }
+
+    // An upper bound on the given percentile: the top of the bucket it falls in
+    fn percentile(&self, p: f64) -> u64 {
+        let rank = ((self.count as f64 * p / 100.0).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (i, &n) in self.buckets.iter().enumerate() {
+            seen += n;
+            if seen >= rank {
+                let top = if i == 0 { 0 } else { u64::MAX >> (64 - i) };
+                return top.min(self.max_us);
+            }
+        }
+        self.max_us
+    }
+
+    fn print(&self, out: &mut impl Write, name: &str) -> io::Result<()> {
+        writeln!(
+            out,
+            "  {}: min {}us, mean {:.1}us, p99 <= {}us, max {}us",
+            name,
+            self.min_us,
+            self.total_us as f64 / self.count as f64,
+            self.percentile(99.0),
+            self.max_us
+        )
+    }
+}
+
+// Running totals for the end-of-game summary
+struct Stats {
+    rounds: u64,
+    bytes_written: u64,
+    started: Instant,
+    scratch_rounds: u64,
+    // Target index -> rounds fired at that target
+    target_rounds: HashMap<usize, u64>,
+    // (target index, candidate index) -> indices of the pages hit in that mapping
+    hits: HashMap<(usize, usize), HashSet<u64>>,
+    // Only filled in with --time-rounds
+    selection_times: Histogram,
+    write_times: Histogram,
+}
+
+impl Stats {
+    fn new() -> Stats {
+        Stats {
+            rounds: 0,
+            bytes_written: 0,
+            started: Instant::now(),
+            scratch_rounds: 0,
+            target_rounds: HashMap::new(),
+            hits: HashMap::new(),
+            selection_times: Histogram::new(),
+            write_times: Histogram::new(),
+        }
+    }
+
+    fn print_summary(
+        &self,
+        out: &mut impl Write,
+        targets: &[TargetProcess],
+        pagesize: u64,
+        units: Units,
+    ) -> io::Result<()> {
+        writeln!(
+            out,
+            "\nYou survived {} round(s), {} {} were overwritten in {:.1}s",
+            self.rounds,
+            units.amount(self.bytes_written),
+            units.name(),
+            self.started.elapsed().as_secs_f64()
+        )?;
+        if self.scratch_rounds > 0 {
+            writeln!(
+                out,
+                "{} of those round(s) only hit scratch pages",
+                self.scratch_rounds
+            )?;
+        }
+        if self.selection_times.count > 0 {
+            writeln!(out, "Round latencies:")?;
+            self.selection_times.print(out, "Selection")?;
+            self.write_times.print(out, "Write")?;
+        }
+
+        for (target_idx, target) in targets.iter().enumerate() {
+            if targets.len() > 1 {
+                writeln!(
+                    out,
+                    "{} ({}): {} round(s)",
+                    target.name(),
+                    if target.alive { "alive" } else { "dead" },
+                    self.target_rounds.get(&target_idx).unwrap_or(&0)
+                )?;
+            }
+            writeln!(
+                out,
+                "  Heap-ish memory: {} {} in {} region(s)",
+                units.amount(target.heap_bytes),
+                units.name(),
+                target.heap_regions
+            )?;
+            let mut hits: Vec<(usize, &HashSet<u64>)> = self
+                .hits
+                .iter()
+                .filter(|((t, _), _)| *t == target_idx)
+                .map(|((_, m), pages)| (*m, pages))
+                .collect();
+            hits.sort_by_key(|&(idx, pages)| (Reverse(pages.len()), idx));
+            for (idx, pages) in hits {
+                let mapping = &target.candidates[idx];
+                writeln!(
+                    out,
+                    "  {}: {}/{} pages hit",
+                    mapping.basename(),
+                    pages.len(),
+                    mapping.size() / pagesize
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    // The same as the summary, for --json-summary. Mappings are in the order they were listed.
+    fn run_summary(&self, targets: &[TargetProcess], pagesize: u64, seed: u64) -> RunSummary {
+        let mut hits: Vec<(&(usize, usize), &HashSet<u64>)> = self.hits.iter().collect();
+        hits.sort_by_key(|&(key, _)| *key);
+        RunSummary {
+            rounds: self.rounds,
+            bytes_written: self.bytes_written,
+            runtime_secs: self.started.elapsed().as_secs_f64(),
+            seed,
+            hits: hits
+                .into_iter()
+                .map(|(&(target_idx, mapping_idx), pages)| {
+                    let target = &targets[target_idx];
+                    let mapping = &target.candidates[mapping_idx];
+                    MappingHits {
+                        pid: target.pid.unwrap_or(process::id()),
+                        start_addr: mapping.start_addr,
+                        path: mapping.path.clone(),
+                        pages_hit: pages.len() as u64,
+                        pages: mapping.size() / pagesize,
+                    }
+                })
+                .collect(),
+        }
+    }
+}
+
+#[cfg(feature = "metrics")]
+impl Stats {
+    // The same counters as the summary, for --metrics-port
+    fn prometheus(&self, targets: &[TargetProcess], seed: u64) -> String {
+        use crate::metrics::label;
+        let mut out = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, samples: Vec<(String, u64)>| {
+            out += &format!("# HELP {} {}\n# TYPE {} {}\n", name, help, name, kind);
+            for (labels, value) in samples {
+                out += &format!("{}{} {}\n", name, labels, value);
+            }
+        };
+        metric(
+            "roulette_rounds_total",
+            "counter",
+            "Rounds fired so far.",
+            vec![(String::new(), self.rounds)],
+        );
+        metric(
+            "roulette_bytes_written_total",
+            "counter",
+            "Bytes overwritten so far.",
+            vec![(String::new(), self.bytes_written)],
+        );
+        metric(
+            "roulette_seed",
+            "gauge",
+            "The seed the game was started with.",
+            vec![(String::new(), seed)],
+        );
+        let mut hits: Vec<(&(usize, usize), &HashSet<u64>)> = self.hits.iter().collect();
+        hits.sort_by_key(|&(key, _)| *key);
+        metric(
+            "roulette_pages_hit",
+            "gauge",
+            "Distinct pages hit in each mapping.",
+            hits.into_iter()
+                .map(|(&(target_idx, mapping_idx), pages)| {
+                    let target = &targets[target_idx];
+                    let mapping = &target.candidates[mapping_idx];
+                    let labels = format!(
+                        "{{target=\"{}\",mapping=\"{}\",start=\"0x{:x}\"}}",
+                        label(&target.name()),
+                        label(mapping.basename()),
+                        mapping.start_addr
+                    );
+                    (labels, pages.len() as u64)
+                })
+                .collect(),
+        );
+        out
+    }
+}
+
+// What a round overwrote, so that it can be undone
+struct Backup {
+    addr: u64,
+    bytes: Vec<u8>,
+    name: String,
+    target_idx: usize,
+    mapping_idx: usize,
+    page_idx: u64,
+    // Whether this was the first hit on the page, i.e. undoing it leaves the page untouched
+    first_hit: bool,
+}
+
+// Read lines from stdin on a separate thread, so the game can give up waiting for them. The
+// channel disconnects at EOF, and also when stdin can't be read any more or isn't text, which the
+// game treats the same way.
+fn spawn_input_reader() -> mpsc::Receiver<String> {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        for line in io::stdin().lines() {
+            let Ok(line) = line else {
+                break;
+            };
+            if tx.send(line).is_err() {
+                break;
+            }
+        }
+    });
+    rx
+}
+
+enum Input {
+    Line(String),
+    Eof,
+    Idle,
+    Interrupted,
+}
+
+// Wait for the next line from the reader thread. We poll rather than block so that a Ctrl-C is
+// noticed even while nobody is typing.
+fn wait_for_input(input: &mpsc::Receiver<String>, idle_timeout: Option<u64>) -> Input {
+    let deadline = idle_timeout.map(|secs| Instant::now() + Duration::from_secs(secs));
+    loop {
+        if interrupt::requested() {
+            return Input::Interrupted;
+        }
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            return Input::Idle;
+        }
+        match input.recv_timeout(Duration::from_millis(100)) {
+            Ok(line) => return Input::Line(line),
+            Err(RecvTimeoutError::Disconnected) => return Input::Eof,
+            Err(RecvTimeoutError::Timeout) => continue,
+        }
+    }
+}
+
+pub fn play(
+    opts: &Options,
+    targets: &mut [TargetProcess],
+    script: Option<&[ScriptedShot]>,
+    pagesize: u64,
+    rng: &mut SeededRng,
+    first_round: u64,
+) -> Result<()> {
+    if targets.iter().all(|t| t.candidates.is_empty()) {
+        return Err(anyhow!("No candidate mappings to play with"));
+    }
+
+    // In sweep mode a single mapping is picked up front and overwritten one page at a time
+    let swept = if opts.sweep {
+        let target_idx = pick_target(targets, false, rng).unwrap();
+        let mapping_idx = rng.random_range(0..targets[target_idx].candidates.len());
+        eprintln!("Sweeping {}", targets[target_idx].candidates[mapping_idx]);
+        Some((target_idx, mapping_idx))
+    } else {
+        None
+    };
+    let progress_inline = io::stderr().is_terminal();
+
+    interrupt::install()?;
+    // Only the ENTER prompt reads stdin, so --auto leaves it alone
+    let input = (!opts.auto).then(spawn_input_reader);
+    let mut stats = Stats::new();
+    // A resumed campaign carries on counting from where it was stopped
+    stats.rounds = first_round;
+    let mut last_shot: Option<Backup> = None;
+    // With --stick, the (target, mapping) that the first shot picked, until it's re-rolled
+    let mut stuck: Option<(usize, usize)> = None;
+    // With --per-mapping-budget, the (target, mapping) having its turn and when the turn started
+    let mut walk: Option<(usize, usize, Instant)> = None;
+    let mut script_pos = 0;
+    // The next shot, picked before the prompt so the player can see it coming
+    let mut pending: Option<Shot> = None;
+    // Shots still to fire from the last ENTER, with --burst
+    let mut burst_left = 0;
+    // With --corruption-ratio, the pages each (target, mapping) may be hit in, picked the first
+    // time it's chosen
+    let mut allowed_pages: HashMap<(usize, usize), Vec<u64>> = HashMap::new();
+    // With --cooldown, the round each (target, mapping, page) was last hit in, and how many picks
+    // in a row have landed on a page that's cooling down
+    let mut last_hit: HashMap<(usize, usize, u64), u64> = HashMap::new();
+    let mut cooldown_retries = 0;
+    // Written a row at a time without buffering, so that dying mid-game leaves every round so far
+    let mut timeline = match &opts.timeline {
+        Some(path) => {
+            let mut file = File::create(path)
+                .context(format!("Failed to create timeline {}", path.display()))?;
+            writeln!(file, "{}", TIMELINE_HEADER)?;
+            Some(file)
+        }
+        None => None,
+    };
+    loop {
+        if interrupt::requested() || opts.rounds.is_some_and(|rounds| stats.rounds >= rounds) {
+            break;
+        }
+        for target in targets.iter_mut() {
+            if target.alive && !target.check_alive() {
+                report_death(opts, target, rng.seed())?;
+            }
+        }
+        if swept.is_some_and(|(target_idx, _)| !targets[target_idx].alive) {
+            break;
+        }
+        if stuck.is_some_and(|(target_idx, _)| !targets[target_idx].alive) {
+            stuck = None;
+        }
+        if let Some(budget) = opts.per_mapping_budget {
+            let due = walk.is_none_or(|(target_idx, _, started)| {
+                started.elapsed() >= Duration::from_secs(budget) || !targets[target_idx].alive
+            });
+            if due {
+                let current = walk.map(|(target_idx, mapping_idx, _)| (target_idx, mapping_idx));
+                let Some((target_idx, mapping_idx)) = next_mapping(targets, current) else {
+                    eprintln!("Every mapping has had its turn");
+                    break;
+                };
+                eprintln!(
+                    "Moving on to {} for {}s",
+                    targets[target_idx].candidates[mapping_idx], budget
+                );
+                walk = Some((target_idx, mapping_idx, Instant::now()));
+                // Whatever was picked for the last mapping doesn't get fired at this one
+                pending = None;
+            }
+        }
+        // A shot picked for a target that has died since is dropped
+        if pending
+            .as_ref()
+            .is_some_and(|shot| !targets[shot.target_idx].alive)
+        {
+            pending = None;
+        }
+        if pending.is_none() {
+            // Picking the shot is timed from here until it's ready to fire
+            let selection_started = Instant::now();
+            // While --min-rounds lasts, only the safe candidates are in play
+            let grace = opts.min_rounds.is_some_and(|rounds| stats.rounds < rounds);
+            let Some((target_idx, grace)) = pick_target_in_grace(targets, grace, rng) else {
+                eprintln!("Every target is dead!");
+                break;
+            };
+            // A script only ever has the one target
+            let scripted = match script {
+                Some(script) => match script.get(script_pos) {
+                    Some(shot) => {
+                        script_pos += 1;
+                        Some(shot)
+                    }
+                    None => {
+                        eprintln!("That's the end of the script");
+                        break;
+                    }
+                },
+                None => None,
+            };
+            // Neither a sweep nor --stick is ever drawn away from its mapping
+            let hot = if scripted.is_none()
+                && swept.is_none()
+                && stuck.is_none()
+                && walk.is_none()
+                && !grace
+            {
+                pick_hot(&targets[target_idx].hot_spots, rng)
+            } else {
+                None
+            };
+            let (target_idx, mapping_idx) = match (scripted, hot) {
+                (Some(shot), _) => (0, shot.mapping_idx),
+                (None, Some(spot)) => (target_idx, spot.mapping_idx),
+                (None, None) if grace => {
+                    (target_idx, *targets[target_idx].safe.choose(rng).unwrap())
+                }
+                (None, None) => swept
+                    .or(stuck)
+                    .or(walk.map(|(target_idx, mapping_idx, _)| (target_idx, mapping_idx)))
+                    .unwrap_or_else(|| {
+                        let mapping_idx = rng.random_range(0..targets[target_idx].candidates.len());
+                        (target_idx, mapping_idx)
+                    }),
+            };
+            if opts.stick && stuck.is_none() && !grace {
+                stuck = Some((target_idx, mapping_idx));
+                eprintln!(
+                    "Sticking to {}",
+                    targets[target_idx].candidates[mapping_idx]
+                );
+            }
+            let mapping = targets[target_idx].candidates[mapping_idx].clone();
+            let page_count = mapping.size() / pagesize;
+            let (start_addr, len) = if let Some(shot) = scripted {
+                (shot.addr, shot.len)
+            } else {
+                let len = write_len(opts, mapping.size(), pagesize, rng);
+                let offset = if swept.is_some() {
+                    if stats.rounds >= page_count {
+                        break;
+                    }
+                    stats.rounds * pagesize
+                } else if let Some(hot) = hot {
+                    // A write that's less than a page starts right on the spot, and a whole page
+                    // is the one the spot is in
+                    let offset = hot.addr - mapping.start_addr;
+                    if opts.write_size.is_some() || opts.bitflip {
+                        offset
+                    } else {
+                        offset & !(pagesize - 1)
+                    }
+                } else if let Some(edge) = opts.edge_pages {
+                    edge_page(edge, page_count, rng) * pagesize
+                } else if opts.resident_only && mapping.path != SCRATCH {
+                    let pid = targets[target_idx].pid.unwrap_or(process::id());
+                    let resident = resident_pages(pid, &mapping, pagesize)?;
+                    let page_idx = match resident.choose(rng) {
+                        Some(&page_idx) => page_idx,
+                        None => {
+                            eprintln!(
+                                "None of {} is resident any more, so any page will do",
+                                mapping.basename()
+                            );
+                            rng.random_range(0..page_count)
+                        }
+                    };
+                    within_page(page_idx * pagesize, len, pagesize, opts, rng)
+                } else if let Some(ratio) = opts.corruption_ratio {
+                    let allowed = allowed_pages
+                        .entry((target_idx, mapping_idx))
+                        .or_insert_with(|| pick_allowed_pages(page_count, ratio, rng));
+                    within_page(
+                        *allowed.choose(rng).unwrap() * pagesize,
+                        len,
+                        pagesize,
+                        opts,
+                        rng,
+                    )
+                } else if opts.write_size.is_some() || opts.bitflip {
+                    rng.random_range(0..=mapping.size() - len)
+                } else {
+                    rng.random_range(0..page_count) * pagesize
+                };
+                (
+                    write_start(&mapping, offset, len, pagesize, opts.align_writes),
+                    len,
+                )
+            };
+            // A page hit too recently is re-rolled, though only so many times, since a small enough
+            // pool may have nothing else to offer
+            let page_idx = (start_addr - mapping.start_addr) / pagesize;
+            if let Some(cooldown) = opts.cooldown
+                && on_cooldown(
+                    last_hit.get(&(target_idx, mapping_idx, page_idx)).copied(),
+                    stats.rounds,
+                    cooldown,
+                )
+                && cooldown_retries < COOLDOWN_RETRIES
+            {
+                cooldown_retries += 1;
+                continue;
+            }
+            cooldown_retries = 0;
+            let bit = opts.bitflip.then(|| rng.random_range(0..8));
+            pending = Some(Shot {
+                target_idx,
+                mapping_idx,
+                mapping,
+                start_addr,
+                len,
+                fill: scripted.map_or(0xFF, |shot| shot.fill),
+                bit,
+                selection_time: selection_started.elapsed(),
+            });
+        }
+
+        if !opts.auto && burst_left == 0 {
+            if !opts.quiet {
+                let shot = pending.as_ref().unwrap();
+                eprintln!(
+                    "Next: {} page {} @ 0x{:X} - 0x{:X}",
+                    victim(opts, &targets[shot.target_idx], &shot.mapping),
+                    (shot.start_addr - shot.mapping.start_addr) / pagesize,
+                    shot.start_addr,
+                    shot.start_addr + shot.len
+                );
+                eprintln!(
+                    "Are you still feeling lucky? Press ENTER to fire \
+                     (s: stats, m: mappings, u: undo last round, r: re-roll, q: quit)"
+                );
+            }
+            let input = input.as_ref().unwrap();
+            let line = match wait_for_input(input, opts.idle_timeout) {
+                Input::Line(line) => line,
+                Input::Idle if opts.idle_action == IdleAction::Fire => {
+                    eprintln!("Nobody's there? Firing anyway...");
+                    String::new()
+                }
+                Input::Idle => {
+                    eprintln!("Nobody's there? Quitting...");
+                    break;
+                }
+                Input::Eof | Input::Interrupted => break,
+            };
+            match line.trim().chars().next() {
+                None => {
+                    burst_left = opts.burst.unwrap_or(1);
+                    if burst_left > 1 && !opts.quiet {
+                        eprintln!("Firing a burst of {} shots", burst_left);
+                    }
+                }
+                Some('s') => {
+                    stats.print_summary(
+                        &mut io::stderr(),
+                        targets,
+                        pagesize,
+                        opts.units(pagesize),
+                    )?;
+                    continue;
+                }
+                Some('m') => {
+                    for target in targets.iter() {
+                        if targets.len() > 1 {
+                            eprintln!("{}:", target.name());
+                        }
+                        for mapping in &target.candidates {
+                            eprintln!("{}", mapping);
+                        }
+                    }
+                    continue;
+                }
+                Some('u') => {
+                    match last_shot.take() {
+                        Some(backup) => {
+                            let target = &targets[backup.target_idx];
+                            // The death itself is reported when the next round starts
+                            let dead =
+                                || !target.alive || target.pid.is_some_and(|pid| !is_alive(pid));
+                            let restored = !dead()
+                                && match write_target(target.pid, backup.addr, &backup.bytes) {
+                                    Ok(()) => true,
+                                    Err(_) if dead() => false,
+                                    Err(e) => return Err(e),
+                                };
+                            if !restored {
+                                eprintln!(
+                                    "{} has died, so there's nothing to undo\n",
+                                    target.name()
+                                );
+                                continue;
+                            }
+                            let key = (backup.target_idx, backup.mapping_idx);
+                            if backup.first_hit
+                                && let Some(pages) = stats.hits.get_mut(&key)
+                            {
+                                pages.remove(&backup.page_idx);
+                                if pages.is_empty() {
+                                    stats.hits.remove(&key);
+                                }
+                            }
+                            eprintln!(
+                                "Restored {} bytes of {} @ 0x{:X}\n",
+                                backup.bytes.len(),
+                                backup.name,
+                                backup.addr
+                            );
+                        }
+                        None => eprintln!("Nothing to undo\n"),
+                    }
+                    continue;
+                }
+                Some('r') => {
+                    // Sweeps and scripts only ever have the one next shot
+                    if swept.is_some() || script.is_some() {
+                        eprintln!("There's nothing to re-roll, the next shot is already decided\n");
+                    } else {
+                        pending = None;
+                        if opts.stick {
+                            stuck = None;
+                            eprintln!("Picking a new mapping to stick to\n");
+                        }
+                    }
+                    continue;
+                }
+                Some('q') => break,
+                Some(c) => {
+                    eprintln!("Unknown command '{}'\n", c);
+                    continue;
+                }
+            }
+        }
+
+        let Shot {
+            target_idx,
+            mapping_idx,
+            mapping,
+            start_addr,
+            len,
+            fill,
+            bit,
+            selection_time,
+        } = pending.take().unwrap();
+        burst_left = burst_left.saturating_sub(1);
+        let target = &mut targets[target_idx];
+        let page_count = mapping.size() / pagesize;
+        let end_addr = start_addr + len;
+        let page_idx = (start_addr - mapping.start_addr) / pagesize;
+
+        if swept.is_some() && !opts.quiet {
+            let progress = format!(
+                "Page {}/{} ({:.1}%) @ 0x{:X}",
+                stats.rounds + 1,
+                page_count,
+                (stats.rounds + 1) as f64 * 100.0 / page_count as f64,
+                start_addr
+            );
+            if progress_inline {
+                eprint!("\r{}", progress);
+            } else {
+                eprintln!("{}", progress);
+            }
+        } else if let Some(bit) = bit
+            && !opts.quiet
+        {
+            eprintln!(
+                "Bang! {} @ 0x{:X}, bit {}\n",
+                victim(opts, target, &mapping),
+                start_addr,
+                bit
+            );
+        } else if !opts.quiet {
+            eprintln!(
+                "Bang! {} @ 0x{:X} - 0x{:X}\n",
+                victim(opts, target, &mapping),
+                start_addr,
+                end_addr
+            );
+        }
+        let len = (end_addr - start_addr) as usize;
+        // The record goes out before the shot, and is flushed rather than left to stdout's
+        // buffering, since the shot that matters most is the one we might not survive
+        let pid = target.pid.unwrap_or(process::id());
+        let record = shot_record(
+            stats.rounds + 1,
+            pid,
+            start_addr,
+            len as u64,
+            bit,
+            opts.round_marker,
+            &mapping,
+        );
+        if opts.format == OutputFormat::Json {
+            println!("{}", record);
+            io::stdout().flush()?;
+        }
+        let write_started = Instant::now();
+        let shot = read_target(target.pid, start_addr, len).and_then(|bytes| {
+            let data = match bit {
+                // What was read is also what goes back, but for the one bit
+                Some(bit) => {
+                    let mut data = bytes.clone();
+                    flip_bit(&mut data, bit as u64);
+                    data
+                }
+                None if opts.round_marker => marked_fill(stats.rounds + 1, fill, len),
+                None => vec![fill; len],
+            };
+            write_target(target.pid, start_addr, &data)?;
+            Ok(bytes)
+        });
+        let write_time = write_started.elapsed();
+        let bytes = match shot {
+            Ok(bytes) => bytes,
+            // The target died under us since the last check, so it just drops out of the pool
+            Err(_) if !target.check_alive() => {
+                report_death(opts, target, rng.seed())?;
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+        if (opts.snapshot_on_crash.is_some() || opts.bundle.is_some()) && target.pid.is_some() {
+            // Mappings we can't read back just go without a snapshot
+            target.snapshot = read_target(target.pid, mapping.start_addr, mapping.size() as usize)
+                .ok()
+                .map(|bytes| Snapshot {
+                    mapping: mapping.clone(),
+                    shot_addr: start_addr,
+                    shot_len: len,
+                    bytes,
+                });
+        }
+        if opts.bundle.is_some() {
+            let script_line =
+                script_line(&target.maps, &mapping, start_addr, len as u64, fill, bit);
+            target.shot_log.push(LoggedShot {
+                record,
+                script_line,
+            });
+        }
+        let first_hit = stats
+            .hits
+            .entry((target_idx, mapping_idx))
+            .or_default()
+            .insert(page_idx);
+        if opts.cooldown.is_some() {
+            last_hit.insert((target_idx, mapping_idx, page_idx), stats.rounds);
+        }
+        last_shot = Some(Backup {
+            addr: start_addr,
+            bytes,
+            name: mapping.basename().to_string(),
+            target_idx,
+            mapping_idx,
+            page_idx,
+            first_hit,
+        });
+        stats.rounds += 1;
+        stats.bytes_written += end_addr - start_addr;
+        if let Some(file) = &mut timeline {
+            let row = timeline_row(
+                stats.rounds,
+                stats.started.elapsed().as_millis(),
+                &mapping,
+                end_addr - start_addr,
+                stats.bytes_written,
+            );
+            writeln!(file, "{}", row)?;
+        }
+        if opts.time_rounds {
+            let selection_us = stats.selection_times.record(selection_time);
+            let write_us = stats.write_times.record(write_time);
+            if !opts.quiet {
+                eprintln!(
+                    "Round {}: selection {}us, write {}us ({} bytes)",
+                    stats.rounds,
+                    selection_us,
+                    write_us,
+                    end_addr - start_addr
+                );
+            }
+        }
+        *stats.target_rounds.entry(target_idx).or_default() += 1;
+        if let Some(path) = &opts.state_file {
+            let state = GameState {
+                seed: rng.seed(),
+                draws: rng.draws(),
+                rounds: stats.rounds,
+            };
+            save_state(path, &state)?;
+        }
+        if mapping.path == SCRATCH {
+            stats.scratch_rounds += 1;
+        }
+        #[cfg(feature = "metrics")]
+        if opts.metrics_port.is_some() {
+            crate::metrics::publish(stats.prometheus(targets, rng.seed()));
+        }
+        if opts.demo && !opts.quiet {
+            thread::sleep(demo::SHOT_PAUSE);
+        }
+    }
+    if swept.is_some() && progress_inline && !opts.quiet {
+        eprintln!();
+    }
+    // A JSON consumer on stdout only wants the shot records and the JSON summary
+    if opts.format == OutputFormat::Json || opts.json_summary {
+        stats.print_summary(&mut io::stderr(), targets, pagesize, opts.units(pagesize))?;
+    } else {
+        stats.print_summary(&mut io::stdout(), targets, pagesize, opts.units(pagesize))?;
+    }
+    if opts.json_summary {
+        println!(
+            "{}",
+            stats.run_summary(targets, pagesize, rng.seed()).to_json()
+        );
+    }
+    Ok(())
+}
+
+// How many times in a row --cooldown re-rolls a pick before taking it anyway
+const COOLDOWN_RETRIES: u32 = 32;
+
+// A shot that has been picked but not fired yet
+struct Shot {
+    target_idx: usize,
+    mapping_idx: usize,
+    mapping: Mapping,
+    start_addr: u64,
+    len: u64,
+    fill: u8,
+    // The bit of the byte at `start_addr` that --bitflip flips, in place of the fill
+    bit: Option<u8>,
+    // How long picking it took, for --time-rounds
+    selection_time: Duration,
+}
+
+// What --round-marker starts every write with: ROUND_MAGIC then the round, both little-endian u32s,
+// so a corrupted region found later says which round hit it. The round wraps past u32::MAX.
+const ROUND_MAGIC: u32 = 0x5244_4e52;
+
+// `len` bytes of fill behind a round marker, which is cut short to fit a write smaller than it
+fn marked_fill(round: u64, fill: u8, len: usize) -> Vec<u8> {
+    let mut data: Vec<u8> = ROUND_MAGIC
+        .to_le_bytes()
+        .into_iter()
+        .chain((round as u32).to_le_bytes())
+        .collect();
+    data.resize(len, fill);
+    data
+}
+
+// Flip bit `bit` of `buf`, counting from the lowest bit of the first byte
+fn flip_bit(buf: &mut [u8], bit: u64) {
+    buf[(bit / 8) as usize] ^= 1 << (bit % 8);
+}
+
+// How a shot's mapping is named to the player: with the process when there's more than one
+fn victim(opts: &Options, target: &TargetProcess, mapping: &Mapping) -> String {
+    match target.pid {
+        Some(pid) if opts.pids.len() > 1 => format!("{} in {}", mapping.basename(), pid),
+        _ => mapping.basename().to_string(),
+    }
+}
+
+// Where in the page at `page_start` a write goes when only some pages may be hit: a write that's
+// less than a page still starts anywhere in it
+fn within_page(
+    page_start: u64,
+    len: u64,
+    pagesize: u64,
+    opts: &Options,
+    rng: &mut impl Rng,
+) -> u64 {
+    if (opts.write_size.is_some() || opts.bitflip) && len < pagesize {
+        page_start + rng.random_range(0..=pagesize - len)
+    } else {
+        page_start
+    }
+}
+
+// How many bytes a round writes into a mapping of `mapping_size` bytes: a page unless --write-size
+// or --bitflip say otherwise, and never more than the mapping holds
+fn write_len(opts: &Options, mapping_size: u64, pagesize: u64, rng: &mut impl Rng) -> u64 {
+    if opts.bitflip {
+        1
+    } else {
+        opts.write_size
+            .map_or(pagesize, |size| size.draw(rng))
+            .min(mapping_size)
+    }
+}
+
+// The pages of a mapping that --corruption-ratio lets be hit, in order: that share of them rounded
+// up, so even a tiny ratio leaves one page. A share that's a whole number give or take rounding
+// error, like 10 * 0.7 coming out as 7.000000000000001, isn't rounded up past it.
+fn pick_allowed_pages(page_count: u64, ratio: f64, rng: &mut impl Rng) -> Vec<u64> {
+    let share = page_count as f64 * ratio;
+    let share = if (share - share.round()).abs() < 1e-9 {
+        share.round()
+    } else {
+        share.ceil()
+    };
+    let count = (share as usize).clamp(1, page_count as usize);
+    let mut pages: Vec<u64> = index::sample(rng, page_count as usize, count)
+        .into_iter()
+        .map(|idx| idx as u64)
+        .collect();
+    pages.sort();
+    pages
+}
+
+// Whether --cooldown still keeps shots off a page that was last hit in round `last_hit`: for the
+// `cooldown` rounds after that one
+fn on_cooldown(last_hit: Option<u64>, round: u64, cooldown: u64) -> bool {
+    last_hit.is_some_and(|hit| round - hit <= cooldown)
+}
+
+// The index of the page --edge-pages picks in a mapping of `page_count` pages
+fn edge_page(edge: EdgePages, page_count: u64, rng: &mut impl Rng) -> u64 {
+    let last = page_count - 1;
+    match edge {
+        EdgePages::First => 0,
+        EdgePages::Last => last,
+        EdgePages::Both => *[0, last].choose(rng).unwrap(),
+    }
+}
+
+// Where a write of `len` bytes that would like to start `offset` bytes into the mapping actually
+// starts. It's pulled back far enough to fit, then rounded down to a page boundary for
+// --align-writes, though never below the start of the mapping.
+fn write_start(mapping: &Mapping, offset: u64, len: u64, pagesize: u64, align: bool) -> u64 {
+    let start = mapping.start_addr + offset.min(mapping.size() - len);
+    if align {
+        (start & !(pagesize - 1)).max(mapping.start_addr)
+    } else {
+        start
+    }
+}
+
+// Announce that a target has died, and save what its last shot hit if we were asked to
+fn report_death(opts: &Options, target: &TargetProcess, seed: u64) -> Result<()> {
+    eprintln!("{} has died!", target.name());
+    if let (Some(path), Some(pid), Some(snapshot)) =
+        (&opts.snapshot_on_crash, target.pid, &target.snapshot)
+    {
+        // Every target gets its own file when there's more than one that could die
+        let path = if opts.pids.len() > 1 {
+            PathBuf::from(format!("{}.{}", path.display(), pid))
+        } else {
+            path.clone()
+        };
+        fs::write(&path, snapshot_bytes(pid, snapshot))
+            .context(format!("Failed to write snapshot to {}", path.display()))?;
+        eprintln!("Saved its last shot mapping to {}", path.display());
+    }
+    if let (Some(dir), Some(pid)) = (&opts.bundle, target.pid) {
+        // The same goes for bundles, which get a directory each
+        let dir = if opts.pids.len() > 1 {
+            dir.join(pid.to_string())
+        } else {
+            dir.clone()
+        };
+        let snapshot = target
+            .snapshot
+            .as_ref()
+            .map(|snapshot| snapshot_bytes(pid, snapshot));
+        write_bundle(&dir, pid, seed, &target.maps, &target.shot_log, snapshot)?;
+        eprintln!("Wrote a reproduction bundle to {}", dir.display());
+    }
+    Ok(())
+}
+
+// A short text header describing the snapshot, then a blank line, then the raw mapping contents
+fn snapshot_bytes(pid: u32, snapshot: &Snapshot) -> Vec<u8> {
+    let mut out = format!(
+        "pid: {}\nmapping: {}\nshot: 0x{:x} - 0x{:x}\nlength: {}\n\n",
+        pid,
+        snapshot.mapping,
+        snapshot.shot_addr,
+        snapshot.shot_addr + snapshot.shot_len as u64,
+        snapshot.bytes.len()
+    )
+    .into_bytes();
+    out.extend_from_slice(&snapshot.bytes);
+    out
+}
+
+// The mapping to walk on to after `current` with --per-mapping-budget: every target's candidates
+// in turn, in order, passing over the targets that have died. None once they've all had a turn.
+fn next_mapping(
+    targets: &[TargetProcess],
+    current: Option<(usize, usize)>,
+) -> Option<(usize, usize)> {
+    let (mut target_idx, mut mapping_idx) = match current {
+        Some((target_idx, mapping_idx)) => (target_idx, mapping_idx + 1),
+        None => (0, 0),
+    };
+    while let Some(target) = targets.get(target_idx) {
+        if target.alive && mapping_idx < target.candidates.len() {
+            return Some((target_idx, mapping_idx));
+        }
+        target_idx += 1;
+        mapping_idx = 0;
+    }
+    None
+}
+
+// Pick a target for the round, and whether it's still played under the grace period. Once no live
+// target has any safe candidates left, the grace period ends early rather than the whole game.
+fn pick_target_in_grace(
+    targets: &[TargetProcess],
+    grace: bool,
+    rng: &mut impl Rng,
+) -> Option<(usize, bool)> {
+    if grace && let Some(target_idx) = pick_target(targets, true, rng) {
+        return Some((target_idx, true));
+    }
+    pick_target(targets, false, rng).map(|target_idx| (target_idx, false))
+}
+
+// Pick a random live target that has something to shoot at
+fn pick_target(targets: &[TargetProcess], grace: bool, rng: &mut impl Rng) -> Option<usize> {
+    let live: Vec<usize> = targets
+        .iter()
+        .enumerate()
+        .filter(|(_, t)| t.alive && !t.candidates.is_empty() && !(grace && t.safe.is_empty()))
+        .map(|(idx, _)| idx)
+        .collect();
+    live.choose(rng).copied()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mapping(start: u64, end: u64) -> Mapping {
+        Mapping::new(start, end, "rw-p".into(), 0, "00:00".into(), 0, "".into())
+    }
+
+    const PAGE: u64 = 0x1000;
+
+    // A target that's really a buffer of ours: with no pid, the game writes to our own memory
+    fn mock_target(memory: &mut [u8]) -> TargetProcess {
+        let start = memory.as_mut_ptr() as u64;
+        let end = start + memory.len() as u64;
+        let mapping = Mapping::new(
+            start,
+            end,
+            "rw-p".into(),
+            0,
+            "00:00".into(),
+            0,
+            "mock".into(),
+        );
+        TargetProcess::new(None, vec![mapping], &[])
+    }
+
+    fn target(mappings: usize, safe: &[usize], alive: bool) -> TargetProcess {
+        let candidates = (0..mappings as u64)
+            .map(|i| mapping(0x10000 * (i + 1), 0x10000 * (i + 1) + 0x4000))
+            .collect();
+        let mut target = TargetProcess::new(Some(1), candidates, &[]);
+        target.safe = safe.to_vec();
+        target.alive = alive;
+        target
+    }
+
+    #[test]
+    fn the_grace_period_ends_once_nothing_safe_is_left() {
+        let mut rng = StdRng::seed_from_u64(7);
+        // Only the second target has anything safe, so grace keeps to it
+        let targets = [target(2, &[], true), target(2, &[1], true)];
+        for _ in 0..20 {
+            assert_eq!(
+                pick_target_in_grace(&targets, true, &mut rng),
+                Some((1, true))
+            );
+        }
+        // Once it's dead, play goes on at the other one, out of grace
+        let targets = [target(2, &[], true), target(2, &[1], false)];
+        assert_eq!(
+            pick_target_in_grace(&targets, true, &mut rng),
+            Some((0, false))
+        );
+        // Without grace the safe lists don't matter
+        assert!(pick_target_in_grace(&targets, false, &mut rng).is_some_and(|(_, grace)| !grace));
+        let targets = [target(2, &[0], false), target(0, &[], true)];
+        assert_eq!(pick_target_in_grace(&targets, true, &mut rng), None);
+    }
+
+    #[test]
+    fn write_start_fits_the_write_into_the_mapping() {
+        // Four pages at 0x10000
+        let m = mapping(0x10000, 0x14000);
+        let page = 0x1000;
+        // Left alone, a write starts where it was asked to
+        assert_eq!(write_start(&m, 0x1234, 8, page, false), 0x11234);
+        assert_eq!(write_start(&m, 0, 8, page, false), 0x10000);
+        // Aligned, it starts at the page that offset is in
+        assert_eq!(write_start(&m, 0x1234, 8, page, true), 0x11000);
+        assert_eq!(write_start(&m, 0x2000, 8, page, true), 0x12000);
+        // A write that would run off the end is pulled back to end with the mapping
+        assert_eq!(write_start(&m, 0x3ffc, 8, page, false), 0x13ff8);
+        assert_eq!(write_start(&m, 0x4000, page, page, false), 0x13000);
+        assert_eq!(write_start(&m, 0x3fff, page, page, true), 0x13000);
+        // A length that isn't a whole number of pages, pulled back and then aligned below that
+        assert_eq!(write_start(&m, 0x3f00, 0x1800, page, false), 0x12800);
+        assert_eq!(write_start(&m, 0x3f00, 0x1800, page, true), 0x12000);
+        // The whole mapping can only start at the start
+        assert_eq!(write_start(&m, 0x2345, 0x4000, page, true), 0x10000);
+        for (offset, len) in [(0, 1), (0x3fff, 1), (0x1001, 0x2fff), (0x3abc, 0x123)] {
+            for align in [false, true] {
+                let start = write_start(&m, offset, len, page, align);
+                assert!(start >= m.start_addr && start + len <= m.end_addr);
+            }
+        }
+    }
+
+    #[test]
+    fn edge_pages_are_the_first_and_last() {
+        let mut rng = StdRng::seed_from_u64(3);
+        assert_eq!(edge_page(EdgePages::First, 16, &mut rng), 0);
+        assert_eq!(edge_page(EdgePages::Last, 16, &mut rng), 15);
+        let both: HashSet<u64> = (0..64)
+            .map(|_| edge_page(EdgePages::Both, 16, &mut rng))
+            .collect();
+        assert_eq!(both, HashSet::from([0, 15]));
+        // In a single page, that page is both
+        for edge in [EdgePages::First, EdgePages::Last, EdgePages::Both] {
+            assert_eq!(edge_page(edge, 1, &mut rng), 0);
+        }
+    }
+
+    #[test]
+    fn writes_never_outgrow_their_mapping() {
+        let mut rng = StdRng::seed_from_u64(11);
+        let mut opts = Options::default();
+        assert_eq!(write_len(&opts, 0x4000, 0x1000, &mut rng), 0x1000);
+        // A mapping smaller than a page only comes from --add-scratch and the like, but still
+        assert_eq!(write_len(&opts, 0x800, 0x1000, &mut rng), 0x800);
+
+        opts.write_size = Some("100-9000".parse().unwrap());
+        for _ in 0..200 {
+            let len = write_len(&opts, 0x2000, 0x1000, &mut rng);
+            assert!((100..=0x2000).contains(&len), "{}", len);
+        }
+        opts.write_size = Some("64".parse().unwrap());
+        assert_eq!(write_len(&opts, 0x2000, 0x1000, &mut rng), 64);
+        assert_eq!(write_len(&opts, 32, 0x1000, &mut rng), 32);
+
+        opts.bitflip = true;
+        assert_eq!(write_len(&opts, 0x2000, 0x1000, &mut rng), 1);
+    }
+
+    #[test]
+    fn corruption_ratios_allow_a_rounded_up_share_of_pages() {
+        let mut rng = StdRng::seed_from_u64(13);
+        for (page_count, ratio, expected) in [
+            (10, 0.5, 5),
+            (10, 0.25, 3),
+            (10, 0.01, 1),
+            (1, 0.5, 1),
+            (7, 1.0, 7),
+            (3, 0.34, 2),
+            (10, 0.7, 7),
+            (100, 0.29, 29),
+        ] {
+            let pages = pick_allowed_pages(page_count, ratio, &mut rng);
+            assert_eq!(pages.len(), expected, "{} pages at {}", page_count, ratio);
+            // A sorted set of real pages, with none twice
+            assert!(pages.windows(2).all(|pair| pair[0] < pair[1]));
+            assert!(pages.iter().all(|&page| page < page_count));
+        }
+    }
+
+    #[test]
+    fn a_page_cools_down_for_the_rounds_after_its_hit() {
+        // Hit in round 10 with --cooldown 3: rounds 11 to 13 are off limits, 14 isn't
+        for round in 11..=13 {
+            assert!(on_cooldown(Some(10), round, 3), "round {}", round);
+        }
+        assert!(!on_cooldown(Some(10), 14, 3));
+        assert!(!on_cooldown(Some(10), 100, 3));
+        // A page never hit is never cooling down, and --cooldown 0 only covers its own round
+        assert!(!on_cooldown(None, 11, 3));
+        assert!(!on_cooldown(Some(10), 11, 0));
+    }
+
+    #[test]
+    fn flipping_a_bit_changes_exactly_that_bit() {
+        let original = [0x00, 0xff, 0x5a, 0x81];
+        for bit in 0..32 {
+            let mut buf = original;
+            flip_bit(&mut buf, bit);
+            let differing: u32 = buf
+                .iter()
+                .zip(&original)
+                .map(|(a, b)| (a ^ b).count_ones())
+                .sum();
+            assert_eq!(differing, 1, "bit {}", bit);
+            assert_eq!(
+                buf[bit as usize / 8] ^ original[bit as usize / 8],
+                1 << (bit % 8)
+            );
+            // And flipping it again puts it back
+            flip_bit(&mut buf, bit);
+            assert_eq!(buf, original);
+        }
+    }
+
+    #[test]
+    fn the_walk_goes_through_every_live_targets_mappings_in_order() {
+        let targets = [
+            target(2, &[], true),
+            target(3, &[], false),
+            target(0, &[], true),
+            target(1, &[], true),
+        ];
+        let mut walk = Vec::new();
+        let mut current = None;
+        while let Some(next) = next_mapping(&targets, current) {
+            walk.push(next);
+            current = Some(next);
+        }
+        // The dead target and the one with no candidates are passed over
+        assert_eq!(walk, [(0, 0), (0, 1), (3, 0)]);
+
+        assert_eq!(next_mapping(&targets, Some((0, 0))), Some((0, 1)));
+        // The end of one target's list carries on at the next live one
+        assert_eq!(next_mapping(&targets, Some((0, 1))), Some((3, 0)));
+        assert_eq!(next_mapping(&targets, Some((3, 0))), None);
+        assert_eq!(next_mapping(&[], None), None);
+        let all_dead = [target(2, &[], false)];
+        assert_eq!(next_mapping(&all_dead, None), None);
+    }
+
+    #[test]
+    fn the_timeline_has_a_row_per_round_with_a_growing_total() {
+        let path =
+            std::env::temp_dir().join(format!("rust_roulette-test-{}-timeline", process::id()));
+        let mut memory = vec![0; 4 * PAGE as usize];
+        let opts = Options {
+            auto: true,
+            rounds: Some(5),
+            timeline: Some(path.clone()),
+            quiet: true,
+            ..Default::default()
+        };
+        let mut targets = [mock_target(&mut memory)];
+        play(&opts, &mut targets, None, PAGE, &mut SeededRng::new(3), 0).unwrap();
+        let text = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let mut lines = text.lines();
+        assert_eq!(lines.next(), Some(TIMELINE_HEADER));
+        let rows: Vec<Vec<&str>> = lines.map(|line| line.split(',').collect()).collect();
+        assert_eq!(rows.len(), 5);
+        let mut last_total = 0;
+        for (i, row) in rows.iter().enumerate() {
+            assert_eq!(row[0], (i + 1).to_string());
+            assert_eq!(row[2], "mock");
+            let total: u64 = row[4].parse().unwrap();
+            assert_eq!(total, last_total + row[3].parse::<u64>().unwrap());
+            assert!(total > last_total);
+            last_total = total;
+        }
+    }
+
+    #[test]
+    fn each_region_starts_with_the_magic_and_round() {
+        let data = marked_fill(0x0102_0304, 0xee, 16);
+        assert_eq!(&data[..4], b"RNDR");
+        assert_eq!(&data[..4], ROUND_MAGIC.to_le_bytes());
+        assert_eq!(&data[4..8], [0x04, 0x03, 0x02, 0x01]);
+        assert_eq!(&data[8..], [0xee; 8]);
+        // Exactly the marker, then cut short, then wrapped past u32::MAX
+        assert_eq!(marked_fill(7, 0, 8)[4..], [7, 0, 0, 0]);
+        assert_eq!(marked_fill(7, 0, 3), b"RND");
+        assert_eq!(marked_fill(u32::MAX as u64 + 2, 0, 8)[4..], [1, 0, 0, 0]);
+
+        // And in a real game every page shot starts with the round that shot it
+        let mut memory = vec![0; 4 * PAGE as usize];
+        let opts = Options {
+            auto: true,
+            rounds: Some(6),
+            round_marker: true,
+            quiet: true,
+            ..Default::default()
+        };
+        let mut targets = [mock_target(&mut memory)];
+        play(&opts, &mut targets, None, PAGE, &mut SeededRng::new(5), 0).unwrap();
+        let mut rounds = Vec::new();
+        for page in memory.chunks(PAGE as usize) {
+            if page.iter().all(|&b| b == 0) {
+                continue;
+            }
+            assert_eq!(&page[..4], b"RNDR");
+            assert!(page[8..].iter().all(|&b| b == 0xff));
+            rounds.push(u32::from_le_bytes(page[4..8].try_into().unwrap()));
+        }
+        // A page shot twice keeps the later round, so the last one is always there
+        rounds.sort();
+        assert!(rounds.windows(2).all(|pair| pair[0] < pair[1]));
+        assert_eq!(rounds.last(), Some(&6));
+    }
+}
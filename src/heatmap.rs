@@ -0,0 +1,154 @@
+use crate::memmap::Mapping;
+use anyhow::{Context, Result, anyhow};
+use rand::Rng;
+use rand::seq::IndexedRandom;
+use std::fs;
+use std::path::Path;
+
+/// A `--heatmap` file: spots that were fatal in earlier runs, one `basename offset weight` per line,
+/// like `libc.so.6 0x1c8000 2.5`. The offset is hex and relative to the lowest address the file is
+/// mapped at, the same as in a `--script`, so it carries over between runs whatever ASLR does.
+/// Spots named more than once add up, so heat maps from several runs can simply be concatenated.
+/// Blank lines and lines starting with # are ignored.
+///
+/// ```
+/// use rust_roulette::heatmap::HeatMap;
+/// use rust_roulette::memmap::Mapping;
+///
+/// let mapping = |start, end, path: &str| Mapping::new(start, end, "r-xp".into(), 0, "fd:00".into(), 1, path.into());
+/// let maps = [mapping(0x10000, 0x11000, "/lib/libfoo.so"), mapping(0x11000, 0x14000, "/lib/libfoo.so")];
+/// let heatmap = HeatMap::parse("libfoo.so 0x1800 2\nlibfoo.so 0x1800 1.5\nlibbar.so 0x0 1\n")?;
+/// let spots = heatmap.resolve(&maps, &maps[1..]);
+/// assert_eq!(spots.len(), 1);
+/// assert_eq!((spots[0].mapping_idx, spots[0].addr, spots[0].weight), (0, 0x11800, 3.5));
+///
+/// assert!(HeatMap::parse("libfoo.so 0x1800 -1").is_err());
+/// assert!(HeatMap::parse("libfoo.so 0x1800 inf").is_err());
+/// assert!(HeatMap::parse("libfoo.so 0x1800 NaN").is_err());
+/// // Each weight is fine on its own, but together they're too big to pick between
+/// assert!(HeatMap::parse("libfoo.so 0x1800 1e308\nlibfoo.so 0x2000 1e308\n").is_err());
+/// // An offset past the end of the address space lands nowhere rather than wrapping around
+/// let far = HeatMap::parse("libfoo.so 0xffffffffffffffff 1")?;
+/// assert!(far.resolve(&maps, &maps).is_empty());
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+pub struct HeatMap {
+    spots: Vec<(String, u64, f64)>,
+}
+
+// A spot of a heat map pinned to an address in one of a target's candidates
+pub struct HotSpot {
+    pub mapping_idx: usize,
+    pub addr: u64,
+    pub weight: f64,
+}
+
+impl HeatMap {
+    pub fn load(path: &Path) -> Result<HeatMap> {
+        let text =
+            fs::read_to_string(path).context(format!("Failed to read {}", path.display()))?;
+        HeatMap::parse(&text).context(format!("Invalid heat map {}", path.display()))
+    }
+
+    pub fn parse(text: &str) -> Result<HeatMap> {
+        let mut spots: Vec<(String, u64, f64)> = Vec::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (basename, offset, weight) = parse_line(line).context(format!("Line {}", i + 1))?;
+            match spots
+                .iter_mut()
+                .find(|(b, o, _)| *b == basename && *o == offset)
+            {
+                Some((_, _, total)) => *total += weight,
+                None => spots.push((basename, offset, weight)),
+            }
+        }
+        if spots.is_empty() {
+            return Err(anyhow!("no spots, so there's nothing to aim at"));
+        }
+        // pick_hot weighs every spot against a shot anywhere, which counts 1
+        let total: f64 = spots.iter().map(|(_, _, weight)| weight).sum::<f64>() + 1.0;
+        if !total.is_finite() {
+            return Err(anyhow!(
+                "the weights add up to more than can be picked between"
+            ));
+        }
+        Ok(HeatMap { spots })
+    }
+
+    // Pin every spot to an address using the target's full map, keeping those that land in one of
+    // the candidates. The rest belong to files this target doesn't map, or mappings it doesn't play.
+    pub fn resolve(&self, mappings: &[Mapping], candidates: &[Mapping]) -> Vec<HotSpot> {
+        self.spots
+            .iter()
+            .filter_map(|(basename, offset, weight)| {
+                let base = mappings
+                    .iter()
+                    .filter(|m| m.basename() == basename)
+                    .map(|m| m.start_addr)
+                    .min()?;
+                let addr = base.checked_add(*offset)?;
+                let mapping_idx = candidates
+                    .iter()
+                    .position(|m| m.basename() == basename && m.contains_addr(addr))?;
+                Some(HotSpot {
+                    mapping_idx,
+                    addr,
+                    weight: *weight,
+                })
+            })
+            .collect()
+    }
+}
+
+fn parse_line(line: &str) -> Result<(String, u64, f64)> {
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    let [basename, offset, weight] = fields[..] else {
+        return Err(anyhow!("expected 'basename offset weight', got '{}'", line));
+    };
+    let offset = u64::from_str_radix(offset.strip_prefix("0x").unwrap_or(offset), 16)
+        .context(format!("Invalid hex offset '{}'", offset))?;
+    let weight: f64 = weight
+        .parse()
+        .context(format!("Invalid weight '{}'", weight))?;
+    if !(weight > 0.0 && weight.is_finite()) {
+        return Err(anyhow!("weight {} isn't a positive number", weight));
+    }
+    Ok((basename.to_string(), offset, weight))
+}
+
+/// Pick a hot spot to aim the next shot at, or None to aim it as usual. Aiming as usual weighs 1,
+/// so a spot of weight 3 is three times as likely as a shot anywhere else.
+///
+/// ```
+/// use rand::SeedableRng;
+/// use rand::rngs::StdRng;
+/// use rust_roulette::heatmap::{HotSpot, pick_hot};
+///
+/// let spots = [
+///     HotSpot { mapping_idx: 0, addr: 0x1000, weight: 8.0 },
+///     HotSpot { mapping_idx: 0, addr: 0x2000, weight: 0.5 },
+/// ];
+/// let mut rng = StdRng::seed_from_u64(1);
+/// let (mut hot, mut cold, mut anywhere) = (0, 0, 0);
+/// for _ in 0..1000 {
+///     match pick_hot(&spots, &mut rng).map(|spot| spot.addr) {
+///         Some(0x1000) => hot += 1,
+///         Some(_) => cold += 1,
+///         None => anywhere += 1,
+///     }
+/// }
+/// assert!(hot > anywhere && anywhere > cold && cold > 0);
+/// ```
+pub fn pick_hot<'a>(spots: &'a [HotSpot], rng: &mut impl Rng) -> Option<&'a HotSpot> {
+    if spots.is_empty() {
+        return None;
+    }
+    let choices: Vec<Option<&HotSpot>> = spots.iter().map(Some).chain([None]).collect();
+    *choices
+        .choose_weighted(rng, |choice| choice.map_or(1.0, |spot| spot.weight))
+        .unwrap()
+}
@@ -0,0 +1,25 @@
+use anyhow::{Context, Result};
+use nix::libc;
+use nix::sys::signal::{SaFlags, SigAction, SigHandler, SigSet, Signal, sigaction};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+
+// Turn Ctrl-C into a request to stop after the current round, so the summary still gets printed
+pub fn install() -> Result<()> {
+    let action = SigAction::new(
+        SigHandler::Handler(handler),
+        SaFlags::empty(),
+        SigSet::empty(),
+    );
+    unsafe { sigaction(Signal::SIGINT, &action) }.context("Failed to install SIGINT handler")?;
+    Ok(())
+}
+
+pub fn requested() -> bool {
+    REQUESTED.load(Ordering::SeqCst)
+}
+
+extern "C" fn handler(_: libc::c_int) {
+    REQUESTED.store(true, Ordering::SeqCst);
+}
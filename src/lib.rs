@@ -0,0 +1,8 @@
+pub mod allowlist;
+pub mod elf;
+pub mod format;
+pub mod heatmap;
+pub mod json;
+pub mod memmap;
+pub mod permconfig;
+pub mod remote;
@@ -1,87 +1,1351 @@
 use anyhow::{Context, Result, anyhow};
+use nix::sys::memfd::{MemFdCreateFlag, memfd_create};
+use nix::sys::mman::{MapFlags, ProtFlags, mmap, mmap_anonymous};
+use nix::sys::signal::Signal;
+use nix::unistd::ftruncate;
 use rand::prelude::*;
-use std::io;
+use rust_roulette::elf::{PT_LOAD, read_program_headers};
+use rust_roulette::format::{
+    ProcessSummary, Units, format_mappings, format_processes, format_raw_vs_parsed, hexdump,
+    perm_stats, sort_mappings, sort_processes,
+};
+use rust_roulette::memmap::*;
+use rust_roulette::remote::{self, request_memmap};
+use std::fs::{self, File};
+use std::num::NonZeroUsize;
+use std::os::unix::net::UnixStream;
+use std::path::Path;
 use std::process;
+use std::thread;
+use std::time::{Duration, Instant};
 
-mod memmap;
-use memmap::*;
+mod args;
+mod bundle;
+mod crash;
+mod demo;
+mod game;
+mod interrupt;
+#[cfg(feature = "metrics")]
+mod metrics;
+mod rng;
+mod script;
+mod selftest;
+use args::{Command, Options};
+use crash::{install_crash_handlers, install_probe_handler, install_violation_handler};
+use game::{SCRATCH, TargetProcess, play};
+use rng::{SeededRng, load_state};
+use script::{parse_script, resolve_script};
+use selftest::selftest;
+mod target;
+use target::{is_alive, read_target, set_pc, write_target};
+mod visualize;
+use visualize::visualize;
 
-fn main() -> Result<()> {
-    let pagesize = match nix::unistd::sysconf(nix::unistd::SysconfVar::PAGE_SIZE) {
-        Ok(Some(val)) => val as u64,
-        Ok(None) => {
-            return Err(anyhow!(
-                "Page size is unlimited??? That's gotta be a big TLB!"
-            ));
+// Poll a process's memory map and print whatever changed since the last poll. This never touches
+// the target's memory, so it's safe to point at anything we can read. Runs until interrupted.
+fn watch(pid: u32) -> Result<()> {
+    let mut previous = get_memmap(pid)?;
+    for mapping in &previous {
+        println!("{}", mapping);
+    }
+    eprintln!("\nWatching {} for changes, press Ctrl-C to stop...", pid);
+
+    loop {
+        thread::sleep(Duration::from_secs(1));
+        let current = get_memmap(pid)?;
+        let changes = diff_maps(&previous, &current);
+        if !changes.is_empty() {
+            println!("\n{} change(s):", changes.len());
+            for change in &changes {
+                println!("{}", change);
+            }
         }
-        Err(x) => return Err(x).context("Failed to get page size"),
-    };
-    println!("Page size is {} bytes", pagesize);
+        previous = current;
+    }
+}
 
-    let mut rng = rand::rng();
+// Summarise the map of every process we can read, for a system-wide look at W+X regions. Processes
+// we aren't allowed to read, or that exit while we look, are left out, and so are kernel threads,
+// which have no map at all.
+fn all_processes(opts: &Options) -> Result<()> {
+    let mut processes = Vec::new();
+    let mut skipped = 0;
+    for pid in all_pids() {
+        let summary = fs::read_to_string(format!("/proc/{}/comm", pid))
+            .context("Failed to read the process name")
+            .and_then(|comm| {
+                let mappings = get_memmap(pid)?;
+                Ok(ProcessSummary::new(
+                    pid,
+                    comm.trim_end().to_string(),
+                    &mappings,
+                ))
+            });
+        match summary {
+            Ok(summary) if summary.mappings == 0 => {}
+            Ok(summary) => processes.push(summary),
+            Err(_) => skipped += 1,
+        }
+    }
+    sort_processes(&mut processes, opts.sort_processes);
+    print!("{}", format_processes(&processes, opts.format)?);
+    if skipped > 0 {
+        eprintln!(
+            "Skipped {} process(es) that couldn't be read or exited during the scan",
+            skipped
+        );
+    }
+    Ok(())
+}
 
-    let my_pid = process::id();
+// Translate an offset into a mapped file to the address it's loaded at. Each segment of a file is
+// mapped from its own file offset, so we look for the segment that covers the requested offset.
+fn resolve(pid: u32, basename: &str, file_offset: u64) -> Result<()> {
+    let mappings = get_memmap(pid)?;
+    let segments: Vec<&Mapping> = mappings
+        .iter()
+        .filter(|m| m.basename() == basename)
+        .collect();
+    if segments.is_empty() {
+        return Err(anyhow!("No mapping of '{}' in process {}", basename, pid));
+    }
+    // The layout comes from the map alone, so this still works, but whatever is on disk now (if
+    // anything) may not be the file that was mapped
+    if let Some(missing) = segments.iter().find(|m| m.backing_file_missing()) {
+        eprintln!(
+            "Warning: {} is no longer on disk, the offset is into the copy that was mapped",
+            missing.path
+        );
+    }
 
-    println!("Enumerating mappings and setting them to writeable...");
-    for mapping in &mut get_memmap(my_pid)? {
-        println!("{}", mapping);
-        if mapping.path.starts_with("[") {
+    let vaddr = resolve_file_offset(&mappings, basename, file_offset).ok_or(anyhow!(
+        "Offset 0x{:x} isn't in any mapped segment of '{}'",
+        file_offset,
+        basename
+    ))?;
+    println!("0x{:x}", vaddr);
+    Ok(())
+}
+
+// Print the program headers of every file mapped into the process, each next to where the part of
+// the file it describes ended up, so the static layout can be lined up with the live one
+fn elf_info(pid: u32) -> Result<()> {
+    let mappings = get_memmap(pid)?;
+    let mut paths: Vec<&str> = Vec::new();
+    for m in &mappings {
+        if m.is_file_backed() && !paths.contains(&m.path.as_str()) {
+            paths.push(&m.path);
+        }
+    }
+    for path in paths {
+        println!("{}", path);
+        let segments: Vec<&Mapping> = mappings.iter().filter(|m| m.path == path).collect();
+        if segments[0].backing_file_missing() {
+            println!("  no longer on disk\n");
+            continue;
+        }
+        let headers = match read_program_headers(Path::new(path)) {
+            Ok(headers) => headers,
+            // Plenty of mapped files aren't ELF at all, like locale archives and caches
+            Err(e) => {
+                println!("  {:#}\n", e);
+                continue;
+            }
+        };
+        // The load bias, from where the first loadable segment's start ended up. Mappings are
+        // sorted, so the first one of the file is where the loader started.
+        let bias = headers
+            .iter()
+            .find(|h| h.p_type == PT_LOAD)
+            .and_then(|first| {
+                let addr = segments[0].file_offset_to_vaddr(first.p_offset)?;
+                addr.checked_sub(first.p_vaddr)
+            });
+        println!(
+            "  {:<14} {:<10} {:<18} {:<5} MAPPED AT",
+            "TYPE", "OFFSET", "VADDR", "FLAGS"
+        );
+        for header in &headers {
+            let mapped = match bias {
+                // Headers that describe no memory, like GNU_STACK, aren't anywhere
+                Some(bias) if header.p_memsz > 0 => {
+                    let addr = bias + header.p_vaddr;
+                    match mappings.iter().find(|m| m.contains_addr(addr)) {
+                        // A segment that isn't where the bias says it should be would mean the
+                        // file on disk isn't the one that was mapped
+                        Some(m)
+                            if m.path == path
+                                && m.vaddr_to_file_offset(addr) != Some(header.p_offset) =>
+                        {
+                            format!("0x{:x} ({}, different file offset)", addr, m.permissions)
+                        }
+                        Some(m) => format!("0x{:x} ({})", addr, m.permissions),
+                        None => format!("0x{:x} (unmapped)", addr),
+                    }
+                }
+                _ => "-".to_string(),
+            };
             println!(
-                "  Skipping {} - special page",
-                mapping.path
+                "  {:<14} 0x{:<8x} 0x{:<16x} {:<5} {}",
+                header.type_name(),
+                header.p_offset,
+                header.p_vaddr,
+                header.flags(),
+                mapped
             );
-            continue;
         }
-        mapping.set_permissions(PermissionSet::from(&"rwxp"))?;
+        println!();
     }
-    println!("");
+    Ok(())
+}
 
-    println!("New mappings:");
-    let mappings = get_memmap(my_pid)?;
-    for mapping in &mappings {
-        println!("{}", mapping);
+// Print a hexdump of [addr, addr + len). The range has to sit inside one readable mapping, which
+// is what makes reading our own memory through a raw pointer safe.
+fn dump(pid: u32, addr: u64, len: u64) -> Result<()> {
+    let mappings = get_memmap(pid)?;
+    let mapping = mappings
+        .iter()
+        .find(|m| m.contains_addr(addr))
+        .ok_or(anyhow!("0x{:x} isn't mapped in process {}", addr, pid))?;
+    if len > mapping.end_addr - addr {
+        return Err(anyhow!(
+            "0x{:x} + {} runs past the end of {}",
+            addr,
+            len,
+            mapping
+        ));
+    }
+    if !mapping.is_readable() {
+        return Err(anyhow!("{} isn't readable", mapping));
     }
 
-    let candidates = &mappings
+    let target = if pid == process::id() {
+        None
+    } else {
+        Some(pid)
+    };
+    let bytes = read_target(target, addr, len as usize)?;
+    print!("{}", hexdump(addr, &bytes));
+    Ok(())
+}
+
+// Map `count` throwaway pages, one mapping each, for the game to hit instead of real data. They're
+// never unmapped, so they stay valid for as long as the game runs.
+fn add_scratch_pages(count: u64, pagesize: u64) -> Result<Vec<Mapping>> {
+    let length = NonZeroUsize::new(pagesize as usize).ok_or(anyhow!("Page size is zero"))?;
+    (0..count)
+        .map(|_| {
+            let ptr = unsafe {
+                mmap_anonymous(
+                    None,
+                    length,
+                    ProtFlags::PROT_READ | ProtFlags::PROT_WRITE,
+                    MapFlags::MAP_PRIVATE,
+                )
+            }
+            .context("Failed to map a scratch page")?;
+            let start = ptr.as_ptr() as u64;
+            Ok(Mapping::new(
+                start,
+                start + pagesize,
+                "rw-p".to_string(),
+                0,
+                "00:00".to_string(),
+                0,
+                SCRATCH.to_string(),
+            ))
+        })
+        .collect()
+}
+
+// Copy the first readable mapping of `basename` into a memfd and map that writeable, for --sandbox.
+// Shots at the copy corrupt real content without any of it being in use. Like the scratch pages,
+// it's never unmapped.
+fn sandbox_copy(mappings: &[Mapping], basename: &str) -> Result<Mapping> {
+    let source = mappings
+        .iter()
+        .find(|m| m.basename() == basename && m.is_readable())
+        .ok_or(anyhow!(
+            "No readable mapping of '{}' to copy into the sandbox",
+            basename
+        ))?;
+    let bytes = read_target(None, source.start_addr, local_addr(source.size())?)?;
+    let fd = memfd_create(c"roulette-sandbox", MemFdCreateFlag::MFD_CLOEXEC)
+        .context("Failed to create a memfd for the sandbox")?;
+    let size = bytes
+        .len()
+        .try_into()
+        .context("The sandbox is too big for a 32-bit file size")?;
+    ftruncate(&fd, size).context("Failed to size the sandbox")?;
+    let length = NonZeroUsize::new(bytes.len()).ok_or(anyhow!("{} is empty", source))?;
+    let ptr = unsafe {
+        mmap(
+            None,
+            length,
+            ProtFlags::PROT_READ | ProtFlags::PROT_WRITE,
+            MapFlags::MAP_SHARED,
+            &fd,
+            0,
+        )
+    }
+    .context("Failed to map the sandbox")?;
+    let start = ptr.as_ptr() as u64;
+    write_target(None, start, &bytes)?;
+    Ok(Mapping::new(
+        start,
+        start + source.size(),
+        "rw-s".to_string(),
+        0,
+        "00:00".to_string(),
+        0,
+        format!("[sandbox:{}]", basename),
+    ))
+}
+
+// Map a file read-only and leave it that way, unlike everything else of ours, so that the first
+// shot into it shows what a real protection fault looks like. It's never unmapped.
+fn map_file_read_only(path: &Path, pagesize: u64) -> Result<Mapping> {
+    let file = File::open(path).context(format!("Failed to open {}", path.display()))?;
+    let len = file
+        .metadata()
+        .context(format!("Failed to stat {}", path.display()))?
+        .len();
+    let length = NonZeroUsize::new(local_addr(len)?).ok_or(anyhow!(
+        "{} is empty, there's nothing to map",
+        path.display()
+    ))?;
+    let ptr = unsafe {
+        mmap(
+            None,
+            length,
+            ProtFlags::PROT_READ,
+            MapFlags::MAP_PRIVATE,
+            &file,
+            0,
+        )
+    }
+    .context(format!("Failed to map {}", path.display()))?;
+    let start = ptr.as_ptr() as u64;
+    let path = fs::canonicalize(path).context(format!("Failed to resolve {}", path.display()))?;
+    Ok(Mapping::new(
+        start,
+        start + len.next_multiple_of(pagesize),
+        "r--p".to_string(),
+        0,
+        "00:00".to_string(),
+        0,
+        path.to_string_lossy().into_owned(),
+    ))
+}
+
+// Send a process off to a random address in one of its mappings that isn't executable, so it
+// faults the moment it carries on. Returns whether it died.
+fn corrupt_pc(pid: u32, rng: &mut impl Rng) -> Result<bool> {
+    let mappings = get_memmap(pid)?;
+    let garbage: Vec<&Mapping> = mappings.iter().filter(|m| !m.is_executable()).collect();
+    let mapping = garbage
+        .choose(rng)
+        .ok_or(anyhow!("Process {} has no non-executable mappings", pid))?;
+    let pc = rng.random_range(mapping.start_addr..mapping.end_addr);
+    let old = set_pc(pid, pc)?;
+    println!(
+        "Process {}: PC 0x{:x} -> 0x{:x} (in {})",
+        pid, old, pc, mapping
+    );
+    // Give the fault a moment to be delivered
+    thread::sleep(Duration::from_millis(100));
+    Ok(!is_alive(pid))
+}
+
+// Overwrite the same spot in every segment of one library in the target, for
+// --corrupt-all-segments. Returns whether that killed it.
+fn corrupt_all_segments(
+    pid: u32,
+    basename: &str,
+    offset: u64,
+    opts: &Options,
+    pagesize: u64,
+    rng: &mut impl Rng,
+) -> Result<bool> {
+    let mappings = get_memmap(pid)?;
+    let spots = segment_offsets(&mappings, basename, offset);
+    if spots.is_empty() {
+        return Err(anyhow!(
+            "Process {} has no segment of '{}' that's more than 0x{:x} bytes long",
+            pid,
+            basename,
+            offset
+        ));
+    }
+    for (segment, addr) in spots {
+        let len = opts
+            .write_size
+            .map_or(pagesize, |size| size.draw(rng))
+            .min(segment.end_addr - addr);
+        write_target(Some(pid), addr, &vec![0xFF; len as usize])?;
+        println!(
+            "Process {}: {} bytes at 0x{:x} (0x{:x} into {} {})",
+            pid, len, addr, offset, segment.permissions, segment.path
+        );
+    }
+    // Give any fault a moment to be delivered
+    thread::sleep(Duration::from_millis(100));
+    Ok(!is_alive(pid))
+}
+
+// Whether a mapping is fair game for the shots
+fn is_candidate(mapping: &Mapping, opts: &Options) -> bool {
+    // Without --skip-kinds, everything in [brackets] sits out, the heap and stack included
+    let skipped = match &opts.skip_kinds {
+        Some(kinds) => kinds.contains(&mapping.kind()),
+        None => mapping.path.starts_with("["),
+    };
+    if skipped {
+        return false;
+    }
+    if opts.no_mprotect && !mapping.is_writable() {
+        return false;
+    }
+    if opts.exclude_heap && mapping.kind() == MappingKind::Heap {
+        return false;
+    }
+    if opts
+        .only
+        .as_ref()
+        .is_some_and(|only| mapping.basename() != only)
+    {
+        return false;
+    }
+    if opts
+        .allowlist
+        .as_ref()
+        .is_some_and(|allowlist| !allowlist.allows(mapping))
+    {
+        return false;
+    }
+    true
+}
+
+// A target's shared mappings whose pages we have mapped shared too, by device and inode and an
+// overlapping range of the file. Private mappings are copy-on-write, so only these carry a shot
+// back to us.
+fn shared_with_self(target: &[Mapping]) -> Result<Vec<&Mapping>> {
+    let ours: Vec<Mapping> = get_memmap(process::id())?
         .into_iter()
-        .filter(|m| !m.path.starts_with("["))
-        .collect::<Vec<Mapping>>();
+        .filter(|m| m.permissions.is_shared())
+        .collect();
+    Ok(target
+        .iter()
+        .filter(|t| {
+            t.permissions.is_shared()
+                && ours.iter().any(|m| {
+                    m.device == t.device
+                        && m.inode == t.inode
+                        && m.offset < t.offset + t.size()
+                        && t.offset < m.offset + m.size()
+                })
+        })
+        .collect())
+}
+
+// Ask a --serve on the --remote socket for the map of the one --pid
+fn remote_memmap(opts: &Options) -> Result<Vec<Mapping>> {
+    let path = opts.remote.as_ref().unwrap();
+    let [pid] = opts.pids[..] else {
+        return Err(anyhow!(
+            "--remote needs exactly one --pid, as the server numbers them"
+        ));
+    };
+    let mut stream =
+        UnixStream::connect(path).context(format!("Failed to connect to {}", path.display()))?;
+    request_memmap(&mut stream, pid)
+}
+
+// With --strict-allowlist, a pattern that matches nothing is a typo or a library this process
+// doesn't load, and either way not the campaign that was planned
+fn check_allowlist(opts: &Options, mappings: &[Mapping], pid: u32) -> Result<()> {
+    if let Some(allowlist) = &opts.allowlist
+        && opts.strict_allowlist
+    {
+        let unmatched = allowlist.unmatched(mappings);
+        if !unmatched.is_empty() {
+            return Err(anyhow!(
+                "Process {} has no mappings matching {} from the allowlist",
+                pid,
+                unmatched.join(", ")
+            ));
+        }
+    }
+    Ok(())
+}
+
+// The start addresses of the stack guard pages in a process, which are left out of the game unless
+// --include-guard-pages says otherwise. Forcing one writeable and then scribbling on it quietly
+// switches off that thread's overflow protection.
+fn find_guard_pages(mappings: &[Mapping], pid: u32, opts: &Options) -> Result<Vec<u64>> {
+    if opts.include_guard_pages {
+        return Ok(Vec::new());
+    }
+    let stacks = thread_stacks(pid)?;
+    Ok(guard_pages(mappings, &stacks)
+        .iter()
+        .map(|g| g.start_addr)
+        .collect())
+}
+
+// What --protect-self keeps out of the game: our own executable's segments and .bss, and the vDSO,
+// which every syscall-free clock read goes through. The executable is matched by the path
+// /proc/self/exe resolves to, which is how maps names it too, build type notwithstanding.
+fn protected_mappings(mappings: &[Mapping]) -> Result<Vec<Mapping>> {
+    let exe = fs::read_link("/proc/self/exe").context("Failed to resolve /proc/self/exe")?;
+    let exe = exe.to_string_lossy();
+    let mut protected: Vec<Mapping> = program_segments(mappings, &exe)
+        .into_iter()
+        .cloned()
+        .collect();
+    if protected.is_empty() {
+        return Err(anyhow!(
+            "None of our mappings are of our own executable {}",
+            exe
+        ));
+    }
+    protected.extend(
+        mappings
+            .iter()
+            .filter(|m| m.kind() == MappingKind::Vdso)
+            .cloned(),
+    );
+    Ok(protected)
+}
+
+// With --protect-argv, cut the pages holding argv and the environment off the top of whichever
+// candidate they're in, which is all but certain to be the [stack]. A shot there rarely kills us,
+// it just makes things odd.
+fn protect_argv(candidates: &mut Vec<Mapping>, pagesize: u64) -> Result<()> {
+    let (start, end) = argv_env_region(process::id())?;
+    let start = start & !(pagesize - 1);
+    candidates.retain_mut(|m| {
+        if m.start_addr >= start && m.start_addr < end {
+            return false;
+        }
+        if m.contains_addr(start) {
+            m.end_addr = start;
+        }
+        true
+    });
+    eprintln!(
+        "Protecting argv and the environment at 0x{:x} - 0x{:x}",
+        start, end
+    );
+    Ok(())
+}
+
+// Pin the --heatmap's spots to where they are in this target. Spots in files it doesn't map, or in
+// mappings it doesn't play, are no use here but may be in the next target, so they're only counted.
+fn aim_hot_spots(opts: &Options, target: &mut TargetProcess, mappings: &[Mapping]) {
+    if let Some(heatmap) = &opts.heatmap {
+        target.hot_spots = heatmap.resolve(mappings, &target.candidates);
+        eprintln!(
+            "{} of the heat map's spots are in process {}",
+            target.hot_spots.len(),
+            target.pid.unwrap_or(process::id())
+        );
+    }
+}
+
+// The mappings that --min-rounds keeps to at first: anonymous rw-p data that isn't heap or a
+// thread's stack and doesn't border a file. An anonymous mapping right after a file's is usually
+// that file's .bss, full of live globals. A stray write is least likely to be noticed anywhere
+// else, but even there it can be, so this only makes an early death less likely.
+fn safe_mappings(mappings: &[Mapping], pid: u32) -> Result<Vec<Mapping>> {
+    let heap = heap_regions(mappings);
+    let stacks = thread_stacks(pid)?;
+    let files: Vec<&Mapping> = mappings.iter().filter(|m| !m.path.is_empty()).collect();
+    Ok(mappings
+        .iter()
+        .filter(|m| {
+            m.path.is_empty()
+                && m.permissions == PermissionSet::from("rw-p")
+                && !heap.iter().any(|h| h.start_addr == m.start_addr)
+                && !stacks.iter().any(|s| s.start_addr == m.start_addr)
+                && !files
+                    .iter()
+                    .any(|f| f.end_addr == m.start_addr || f.start_addr == m.end_addr)
+        })
+        .cloned()
+        .collect())
+}
+
+// Which of the candidates hold a safe mapping, with our scratch pages the safest of all
+fn safe_indices(candidates: &[Mapping], safe: &[Mapping]) -> Vec<usize> {
+    candidates
+        .iter()
+        .enumerate()
+        .filter(|(_, m)| m.path == SCRATCH || safe.iter().any(|s| m.contains_addr(s.start_addr)))
+        .map(|(idx, _)| idx)
+        .collect()
+}
+
+// Swap the candidates for the likely TLS regions inside them, for --target-tls. Regions are matched
+// by containment, since some are slices of a stack and our own mappings may have merged once they
+// were all made rwx.
+fn keep_tls(candidates: &mut Vec<Mapping>, tls: Vec<Mapping>, pid: u32) -> Result<()> {
+    let kept: Vec<Mapping> = tls
+        .into_iter()
+        .filter(|t| {
+            candidates
+                .iter()
+                .any(|m| m.contains_addr(t.start_addr) && t.end_addr <= m.end_addr)
+        })
+        .collect();
+    if kept.is_empty() {
+        return Err(anyhow!(
+            "Found no likely TLS regions in process {}; only threads other than the main one have them",
+            pid
+        ));
+    }
+    *candidates = kept;
+    Ok(())
+}
+
+// Seccomp, SELinux and memory-deny-write-execute can all leave a mapping short of the permissions
+// we asked for, so go by what the map says afterwards rather than by what mprotect returned. A
+// mapping that didn't take is one whose writes will fault later.
+fn check_permissions(requested: &[(Mapping, PermissionSet)]) -> Result<()> {
+    let current = get_memmap(process::id())?;
+    let mut mismatched = 0;
+    for (mapping, perms) in requested {
+        // mprotect can split or merge mappings, so check every part of the range we changed
+        let wrong = current
+            .iter()
+            .filter(|m| m.overlaps(mapping) && m.permissions != *perms);
+        for m in wrong {
+            eprintln!(
+                "Warning: 0x{:x}-0x{:x} {} is {}, not the {} we asked for",
+                m.start_addr, m.end_addr, m.path, m.permissions, perms
+            );
+            mismatched += 1;
+        }
+    }
+    if mismatched > 0 {
+        eprintln!(
+            "Warning: {} mapping(s) didn't take their new permissions, probably a security policy. \
+             Writes to them may fault",
+            mismatched
+        );
+    }
+    Ok(())
+}
+
+// How much of each candidate is in RAM, since a shot into pages nothing has touched yet is far less
+// likely to matter than one into live data
+fn print_residency(candidates: &[Mapping], pid: u32, pagesize: u64) {
+    eprintln!("Resident pages of the candidates:");
+    for mapping in candidates {
+        match resident_ratio(pid, mapping, pagesize) {
+            Ok(ratio) => eprintln!("  {:>5.1}%  {}", ratio * 100.0, mapping),
+            Err(e) => eprintln!("      ?   {} ({:#})", mapping, e),
+        }
+    }
+    eprintln!();
+}
+
+// Keep only the candidates with a page in RAM, for --resident-only
+fn keep_resident(candidates: &mut Vec<Mapping>, pid: u32, pagesize: u64) -> Result<()> {
+    let mut kept = Vec::new();
+    for mapping in candidates.drain(..) {
+        if !resident_pages(pid, &mapping, pagesize)?.is_empty() {
+            kept.push(mapping);
+        }
+    }
+    if kept.is_empty() {
+        return Err(anyhow!(
+            "None of process {}'s candidate pages are resident",
+            pid
+        ));
+    }
+    *candidates = kept;
+    Ok(())
+}
+
+// Guesses at the chance that overwriting a random page of each kind of mapping kills the process
+fn default_fatality(kind: MappingKind) -> f64 {
+    match kind {
+        MappingKind::Code => 0.5,
+        MappingKind::Data => 0.2,
+        MappingKind::Heap => 0.3,
+        MappingKind::Stack => 0.5,
+        MappingKind::Anonymous => 0.05,
+        MappingKind::Vdso | MappingKind::Vvar | MappingKind::Vsyscall => 1.0,
+        MappingKind::Special => 1.0,
+    }
+}
+
+// A back-of-the-envelope estimate of how long the target survives. Each round picks a candidate
+// mapping uniformly (regardless of its size) and then a page within it, and we pretend that a
+// shot into a mapping of kind k is fatal with a fixed probability f(k), independently of every
+// earlier round. The chance of dying in a round is then the average of f over the candidates, and
+// the number of rounds until the first fatal one is geometric with mean 1/p. Real crashes depend
+// heavily on which pages are actually in use, so take the number with a large pinch of salt.
+fn estimate(candidates: &[Mapping], overrides: &[(MappingKind, f64)]) -> Result<()> {
+    if candidates.is_empty() {
+        return Err(anyhow!("No candidate mappings to estimate over"));
+    }
+    let fatality = |kind: MappingKind| {
+        overrides
+            .iter()
+            .find(|(k, _)| *k == kind)
+            .map_or(default_fatality(kind), |(_, f)| *f)
+    };
 
     println!(
-        "\nWelcome to Rust Roulette! It's a daring game where pages of memory are overwritten "
+        "Assuming each shot picks one of {} mappings uniformly:",
+        candidates.len()
     );
-    println!("until something terrible happens!\n");
-    println!("'Some of you may die, but it's a sacrifice I'm willing to make' - J. Lithgow\n");
-    loop {
-        println!("Are you still feeling lucky? Press ENTER to play 1 more round");
-        let mut input = String::new();
-        io::stdin()
-            .read_line(&mut input)
-            .expect("Waiting for user input");
-
-        let mapping = &candidates.choose(&mut rng).unwrap();
-        let page_count = mapping.size() / pagesize;
-        let page_idx = rng.random_range(0..page_count);
-        let start_addr = mapping.start_addr + pagesize * page_idx;
-        let end_addr = start_addr + pagesize;
+    let mut kinds: Vec<MappingKind> = candidates.iter().map(|m| m.kind()).collect();
+    kinds.sort_by_key(|k| k.to_string());
+    kinds.dedup();
+    for kind in kinds {
+        let of_kind: Vec<&Mapping> = candidates.iter().filter(|m| m.kind() == kind).collect();
+        println!(
+            "  {:<10} {:>4} mapping(s) {:>14} bytes, {:.0}% chance a shot is fatal",
+            kind,
+            of_kind.len(),
+            of_kind.iter().map(|m| m.size()).sum::<u64>(),
+            fatality(kind) * 100.0
+        );
+    }
 
+    let p = candidates.iter().map(|m| fatality(m.kind())).sum::<f64>() / candidates.len() as f64;
+    if p == 0.0 {
+        println!("\nNo shot can ever be fatal under these assumptions");
+    } else {
         println!(
-            "Bang! {} @ 0x{:X} - 0x{:X}\n",
-            mapping
-                .path
-                .split('/')
-                .last()
-                .expect("failed to spilt path"),
-            start_addr,
-            end_addr
+            "\n{:.1}% chance of a crash per round, so expect to survive about {:.1} round(s)",
+            p * 100.0,
+            1.0 / p - 1.0
         );
-        for addr in start_addr..end_addr {
-            let addr = addr as *mut u8;
-            unsafe {
-                *addr = 0xFF;
+    }
+    Ok(())
+}
+
+// Revoke all access to a random mapping and report the first page that gets touched. Nothing is
+// overwritten, but whatever touches the mapping still dies, so this only makes sense for
+// ourselves: the report comes from our own SIGSEGV handler.
+fn probe(pagesize: u64, rng: &mut impl Rng) -> Result<()> {
+    let candidates: Vec<Mapping> = get_memmap(process::id())?
+        .into_iter()
+        .filter(|m| !m.path.starts_with("["))
+        .collect();
+    let mut mapping = candidates
+        .choose(rng)
+        .ok_or(anyhow!("No mappings to probe"))?
+        .clone();
+
+    eprintln!("Probing {}, waiting for it to be touched...", mapping);
+    install_probe_handler(mapping.start_addr, mapping.end_addr, pagesize)?;
+    mapping.set_permissions(PermissionSet::from("---p"))?;
+
+    // Keep doing ordinary work so that something eventually wanders into the probed mapping
+    loop {
+        get_memmap(process::id())?;
+        eprintln!("Still waiting...");
+        thread::sleep(Duration::from_secs(1));
+    }
+}
+
+// How the run ended, as the process exit status
+#[derive(Clone, Copy)]
+enum Exit {
+    // Quit, EOF, out of rounds, or a command that did its job
+    Clean = 0,
+    // Something went wrong once the game was under way, or selftest found a problem
+    Error = 1,
+    // We never got going: bad arguments, no candidates, no procfs...
+    Setup = 2,
+    // A --pid target died. We can't report our own death this way, since there's nobody left to.
+    TargetDied = 3,
+}
+
+fn main() {
+    let exit = run().unwrap_or_else(|e| {
+        eprintln!("Error: {:?}", e);
+        Exit::Setup
+    });
+    process::exit(exit as i32);
+}
+
+// Errors returned from here count as setup errors; ones from the game itself are handled inside
+fn run() -> Result<Exit> {
+    let opts = Options::parse(std::env::args().skip(1))?;
+    if let Some(pid) = opts.watch {
+        watch(pid)?;
+        return Ok(Exit::Clean);
+    }
+    if opts.all_processes {
+        all_processes(&opts)?;
+        return Ok(Exit::Clean);
+    }
+    if let Some(Command::Resolve { basename, offset }) = &opts.command {
+        resolve(opts.single_target()?, basename, *offset)?;
+        return Ok(Exit::Clean);
+    }
+    if let Some(Command::Dump { addr, len }) = &opts.command {
+        dump(opts.single_target()?, *addr, *len)?;
+        return Ok(Exit::Clean);
+    }
+    if let Some(Command::Diff { old, new }) = &opts.command {
+        let changes = diff_maps(&load_memmap(old)?, &load_memmap(new)?);
+        if changes.is_empty() {
+            eprintln!("No differences");
+        }
+        for change in &changes {
+            println!("{}", change);
+        }
+        return Ok(Exit::Clean);
+    }
+    if let Some(Command::Selftest) = opts.command {
+        return Ok(if selftest()? {
+            Exit::Clean
+        } else {
+            Exit::Error
+        });
+    }
+    if opts.elf_info {
+        elf_info(opts.single_target()?)?;
+        return Ok(Exit::Clean);
+    }
+    if let Some(path) = &opts.serve {
+        remote::serve(path)?;
+        return Ok(Exit::Clean);
+    }
+    let enumerate = opts.list || opts.visualize || opts.perm_stats;
+    if opts.remote.is_some() && (opts.maps_path.is_some() || !enumerate) {
+        return Err(anyhow!(
+            "--remote is where --list, --visualize and --perm-stats read the map from, instead of --maps-path"
+        ));
+    }
+    if enumerate {
+        let mappings = match &opts.maps_path {
+            Some(path) => load_memmap(path)?,
+            None if opts.remote.is_some() => remote_memmap(&opts)?,
+            None if opts.list && opts.verbose >= 2 => {
+                get_memmap_with_raw_lines(opts.single_target()?)?
+            }
+            None => get_memmap(opts.single_target()?)?,
+        };
+        // Only pages need the page size, and a --maps-path may come from anywhere
+        let units = if opts.size_in_pages {
+            opts.units(detect_page_size(opts.page_size)?)
+        } else {
+            Units::Bytes
+        };
+        if opts.list {
+            // The visualisation is laid out by address whatever the listing is sorted by
+            let mut listed = mappings.clone();
+            sort_mappings(&mut listed, opts.sort_by);
+            if opts.verbose >= 2 {
+                print!("{}", format_raw_vs_parsed(&listed));
+            } else {
+                print!("{}", format_mappings(&listed, opts.format, units));
+            }
+        }
+        if opts.visualize {
+            visualize(&mappings);
+        }
+        if opts.perm_stats {
+            for (perms, count, bytes) in perm_stats(&mappings) {
+                println!(
+                    "{}  {:>4} mapping(s) {:>14} {}",
+                    perms,
+                    count,
+                    units.amount(bytes),
+                    units.name()
+                );
+            }
+        }
+        return Ok(Exit::Clean);
+    }
+
+    // The live game's page maths, mprotect and mmap all have to agree with the kernel's pages, so
+    // an override is only for reading a map taken somewhere else
+    if let Some(size) = opts.page_size {
+        return Err(anyhow!(
+            "--page-size {} is only for --list, --visualize and --perm-stats; playing always uses \
+             the system's page size",
+            size
+        ));
+    }
+    let pagesize = detect_page_size(None)?;
+    eprintln!("Page size is {} bytes", pagesize);
+    // Nothing depends on this, it only helps make sense of how reproducible a game is
+    if let Ok(aslr) = aslr_state() {
+        eprintln!("{}", aslr);
+    }
+
+    let resumed = match &opts.state_file {
+        Some(path) => load_state(path)?,
+        None => None,
+    };
+    let mut rng = match &resumed {
+        Some(state) => {
+            if opts.seed.is_some_and(|seed| seed != state.seed) {
+                return Err(anyhow!(
+                    "--seed doesn't match the seed {} saved in the state file",
+                    state.seed
+                ));
+            }
+            eprintln!(
+                "Resuming after round {} ({} random draws in)",
+                state.rounds, state.draws
+            );
+            SeededRng::resume(state.seed, state.draws)
+        }
+        None => SeededRng::new(opts.seed.unwrap_or_else(rand::random)),
+    };
+    eprintln!("Seed is {}", rng.seed());
+
+    if opts.probe {
+        if !opts.pids.is_empty() {
+            return Err(anyhow!(
+                "--probe can only observe this process, not --pid targets"
+            ));
+        }
+        probe(pagesize, &mut rng)?;
+        return Ok(Exit::Clean);
+    }
+    if opts.state_file.is_some() && opts.sweep {
+        return Err(anyhow!(
+            "--state-file can't resume a --sweep, which picks its mapping before the first round"
+        ));
+    }
+    if opts.script.is_some() && (opts.sweep || opts.stick) {
+        return Err(anyhow!(
+            "--script decides where every shot goes, so it can't be used with --sweep or --stick"
+        ));
+    }
+    if let Some(ratio) = opts.corruption_ratio {
+        if !(ratio > 0.0 && ratio <= 1.0) {
+            return Err(anyhow!(
+                "--corruption-ratio {} isn't a fraction in (0, 1]",
+                ratio
+            ));
+        }
+        if opts.sweep || opts.script.is_some() || opts.edge_pages.is_some() {
+            return Err(anyhow!(
+                "--sweep, --script and --edge-pages pick their own pages, so they can't be used with --corruption-ratio"
+            ));
+        }
+    }
+    if opts.resident_only
+        && (opts.sweep
+            || opts.script.is_some()
+            || opts.edge_pages.is_some()
+            || opts.corruption_ratio.is_some())
+    {
+        return Err(anyhow!(
+            "--sweep, --script, --edge-pages and --corruption-ratio pick their own pages, so they can't be used with --resident-only"
+        ));
+    }
+    if opts.per_mapping_budget.is_some() && (opts.sweep || opts.stick || opts.script.is_some()) {
+        return Err(anyhow!(
+            "--per-mapping-budget picks its own mappings, so it can't be used with --sweep, --stick or --script"
+        ));
+    }
+    if opts.heatmap.is_some() && (opts.sweep || opts.script.is_some()) {
+        return Err(anyhow!(
+            "--sweep and --script decide every shot, so there's nothing for a --heatmap to aim"
+        ));
+    }
+    if opts.cooldown.is_some() && (opts.sweep || opts.script.is_some()) {
+        return Err(anyhow!(
+            "--sweep and --script never pick a page at random, so --cooldown has nothing to re-roll"
+        ));
+    }
+    if opts.bitflip && opts.round_marker {
+        return Err(anyhow!(
+            "--bitflip only changes one bit, which leaves no room for a --round-marker"
+        ));
+    }
+    if opts.bitflip && (opts.script.is_some() || opts.write_size.is_some()) {
+        return Err(anyhow!(
+            "--bitflip always hits a single byte, so it can't be used with --script or --write-size"
+        ));
+    }
+    if opts.min_rounds.is_some() && (opts.sweep || opts.script.is_some()) {
+        return Err(anyhow!(
+            "--sweep and --script decide every shot, so they can't be used with --min-rounds"
+        ));
+    }
+    if opts.burst == Some(0) {
+        return Err(anyhow!("--burst needs at least 1 shot"));
+    }
+    if opts.burst.is_some() && opts.auto {
+        return Err(anyhow!(
+            "--burst is for the ENTER prompt, so it can't be used with --auto"
+        ));
+    }
+    if opts.make_perms.is_some() && (opts.no_mprotect || !opts.pids.is_empty()) {
+        return Err(anyhow!(
+            "--make-perms sets our own permissions, so it can't be used with --no-mprotect or --pid"
+        ));
+    }
+    if opts.perm_config.is_some() && (opts.no_mprotect || !opts.pids.is_empty()) {
+        return Err(anyhow!(
+            "--perm-config sets our own permissions, so it can't be used with --no-mprotect or --pid"
+        ));
+    }
+    if opts.strict_allowlist && opts.allowlist.is_none() {
+        return Err(anyhow!("--strict-allowlist needs an --allowlist to check"));
+    }
+    if (opts.protect_self || opts.protect_argv) && !opts.pids.is_empty() {
+        return Err(anyhow!(
+            "--protect-self and --protect-argv are about our own mappings, so they can't be used with --pid"
+        ));
+    }
+    if opts.perms_only && opts.no_mprotect {
+        return Err(anyhow!(
+            "--perms-only times the mprotect calls that --no-mprotect skips"
+        ));
+    }
+    if opts.perms_only && !opts.pids.is_empty() {
+        return Err(anyhow!(
+            "--perms-only changes our own permissions, so it can't be used with --pid"
+        ));
+    }
+    if opts.catch_signals.is_some() && !opts.pids.is_empty() {
+        return Err(anyhow!(
+            "--catch-signals reports our own signals, so it can't be used with --pid"
+        ));
+    }
+    if opts.mmap_file.is_some() && !opts.pids.is_empty() {
+        return Err(anyhow!(
+            "--mmap-file maps the file into this process, so it can't be used with --pid"
+        ));
+    }
+    if opts.add_scratch.is_some() && !opts.pids.is_empty() {
+        return Err(anyhow!(
+            "--add-scratch maps pages into this process, so it can't be used with --pid"
+        ));
+    }
+    if opts.snapshot_on_crash.is_some() && opts.pids.is_empty() {
+        return Err(anyhow!(
+            "--snapshot-on-crash needs --pid targets, there's no saving anything once we've died"
+        ));
+    }
+    if opts.sandbox.is_some() && (!opts.pids.is_empty() || opts.perms_only) {
+        return Err(anyhow!(
+            "--sandbox only shoots at a copy in this process, and never changes any permissions"
+        ));
+    }
+    if opts.bundle.is_some() && opts.pids.is_empty() {
+        return Err(anyhow!(
+            "--bundle needs --pid targets, there's no writing anything once we've died"
+        ));
+    }
+    if opts.corrupt_pc {
+        if opts.pids.is_empty() {
+            return Err(anyhow!(
+                "--corrupt-pc is only for --pid targets, we'd be pulling the rug from under ourselves"
+            ));
+        }
+        if !opts.yes {
+            return Err(anyhow!(
+                "--corrupt-pc crashes every --pid target outright, add --yes if that's what you want"
+            ));
+        }
+        let mut exit = Exit::Clean;
+        for &pid in &opts.pids {
+            if corrupt_pc(pid, &mut rng)? {
+                eprintln!("Process {} has died!", pid);
+                exit = Exit::TargetDied;
+            } else {
+                eprintln!("Process {} is somehow still alive", pid);
             }
         }
+        return Ok(exit);
     }
+    if let Some((basename, offset)) = &opts.corrupt_all_segments {
+        if opts.pids.is_empty() {
+            return Err(anyhow!(
+                "--corrupt-all-segments is for --pid targets, whose protections the kernel writes through"
+            ));
+        }
+        let mut exit = Exit::Clean;
+        for &pid in &opts.pids {
+            if corrupt_all_segments(pid, basename, *offset, &opts, pagesize, &mut rng)? {
+                eprintln!("Process {} has died!", pid);
+                exit = Exit::TargetDied;
+            }
+        }
+        return Ok(exit);
+    }
+    if let Some(port) = opts.metrics_port {
+        #[cfg(feature = "metrics")]
+        metrics::serve(port)?;
+        #[cfg(not(feature = "metrics"))]
+        return Err(anyhow!(
+            "--metrics-port {} needs a build with the metrics feature",
+            port
+        ));
+    }
+    if opts.estimate {
+        let pid = opts.single_target()?;
+        let mappings = get_memmap(pid)?;
+        check_allowlist(&opts, &mappings, pid)?;
+        let guards = find_guard_pages(&mappings, pid, &opts)?;
+        let mut candidates: Vec<Mapping> = mappings
+            .into_iter()
+            .filter(|m| is_candidate(m, &opts) && !guards.contains(&m.start_addr))
+            .collect();
+        if opts.target_tls {
+            keep_tls(&mut candidates, tls_regions(pid)?, pid)?;
+        }
+        estimate(&candidates, &opts.fatality)?;
+        return Ok(Exit::Clean);
+    }
+
+    let mut targets = Vec::new();
+    if opts.pids.is_empty() {
+        eprintln!("Enumerating mappings...");
+        let mut original = get_memmap(process::id())?;
+        check_allowlist(&opts, &original, process::id())?;
+        // The arena heuristic goes by permissions, so it has to look before everything is rwx
+        let heap: Vec<Mapping> = heap_regions(&original).into_iter().cloned().collect();
+        let tls = if opts.target_tls {
+            Some(tls_regions(process::id())?)
+        } else {
+            None
+        };
+        // Likewise guard pages, which stop looking like guard pages once they're accessible
+        let guards = find_guard_pages(&original, process::id(), &opts)?;
+        let safe = if opts.min_rounds.is_some() {
+            safe_mappings(&original, process::id())?
+        } else {
+            Vec::new()
+        };
+        // Made rwx, our segments can merge into one mapping, so this goes by range rather than start
+        let protected = if opts.protect_self {
+            protected_mappings(&original)?
+        } else {
+            Vec::new()
+        };
+        // Writes to our own pages need them writeable, unless we stick to those that already are
+        if let Some(basename) = &opts.sandbox {
+            eprintln!(
+                "Only shooting at a copy of {}, so our permissions stay as they are",
+                basename
+            );
+        } else if opts.no_mprotect {
+            eprintln!("Leaving our permissions alone, so only writeable mappings are targets");
+        } else {
+            let make_perms = opts
+                .make_perms
+                .clone()
+                .unwrap_or(PermissionSet::from("rwxp"));
+            // Only the mprotect calls themselves are timed, not the printing around them
+            let mut mprotect_time = Duration::ZERO;
+            let mut changed = 0;
+            let mut failed = Vec::new();
+            let mut requested = Vec::new();
+            for mapping in &mut original {
+                eprintln!("{}", mapping);
+                let configured = opts
+                    .perm_config
+                    .as_ref()
+                    .and_then(|config| config.lookup(mapping))
+                    .cloned();
+                // A special page is only touched if the config names it
+                if mapping.path.starts_with("[") && configured.is_none() {
+                    eprintln!("  Skipping {} - special page", mapping.path);
+                    continue;
+                }
+                if guards.contains(&mapping.start_addr) {
+                    eprintln!("  Skipping stack guard page");
+                    continue;
+                }
+                let started = Instant::now();
+                // Taking execute away from our own code would kill us on the spot
+                let perms = configured.unwrap_or(make_perms.clone());
+                let perms = if mapping.is_executable() {
+                    perms.with_executable()
+                } else {
+                    perms
+                };
+                let result = mapping.set_permissions(perms.clone());
+                mprotect_time += started.elapsed();
+                match result {
+                    Ok(()) => {
+                        changed += 1;
+                        requested.push((
+                            mapping.clone(),
+                            perms.with_shared(mapping.permissions.is_shared()),
+                        ));
+                    }
+                    // Benchmarking wants the whole picture rather than the first failure
+                    Err(e) if opts.perms_only => failed.push((mapping.to_string(), e)),
+                    Err(e) => return Err(e),
+                }
+            }
+            eprintln!();
+            check_permissions(&requested)?;
+
+            if opts.perms_only {
+                println!(
+                    "Changed {} mapping(s) in {:.3}ms, {} failed, {} special or guard skipped",
+                    changed,
+                    mprotect_time.as_secs_f64() * 1000.0,
+                    failed.len(),
+                    original.len() - changed - failed.len()
+                );
+                for (mapping, e) in &failed {
+                    println!("  {}: {:#}", mapping, e);
+                }
+                return Ok(Exit::Clean);
+            }
+            eprintln!("New mappings:");
+        }
+        let mappings = get_memmap(process::id())?;
+        for mapping in &mappings {
+            eprintln!("{}", mapping);
+        }
+        let mut candidates: Vec<Mapping> = mappings
+            .into_iter()
+            .filter(|m| is_candidate(m, &opts) && !guards.contains(&m.start_addr))
+            .filter(|m| !protected.iter().any(|p| p.overlaps(m)))
+            .collect();
+        if !protected.is_empty() {
+            eprintln!(
+                "Protecting {} mapping(s) of our executable and the vDSO",
+                protected.len()
+            );
+        }
+        if let Some(tls) = tls {
+            keep_tls(&mut candidates, tls, process::id())?;
+        }
+        if opts.protect_argv {
+            protect_argv(&mut candidates, pagesize)?;
+        }
+        if opts.resident_only {
+            keep_resident(&mut candidates, process::id(), pagesize)?;
+        }
+        if let Some(basename) = &opts.sandbox {
+            let copy = sandbox_copy(&original, basename)?;
+            eprintln!("Copied {} into {}", basename, copy);
+            candidates = vec![copy];
+        }
+        if let Some(count) = opts.add_scratch {
+            candidates.extend(add_scratch_pages(count, pagesize)?);
+        }
+        let signals = opts
+            .catch_signals
+            .clone()
+            .unwrap_or(vec![Signal::SIGSEGV, Signal::SIGBUS]);
+        install_crash_handlers(&signals)?;
+        // This takes over SIGSEGV, since it can say more about the faults it expects
+        if let Some(path) = &opts.mmap_file {
+            let mapping = map_file_read_only(path, pagesize)?;
+            eprintln!("Mapped {} read-only, and it's staying that way", mapping);
+            install_violation_handler(mapping.start_addr, mapping.end_addr, &mapping.path)?;
+            candidates.push(mapping);
+        }
+        print_residency(&candidates, process::id(), pagesize);
+        let heap: Vec<&Mapping> = heap.iter().collect();
+        let mut target = TargetProcess::new(None, candidates, &heap);
+        target.safe = safe_indices(&target.candidates, &safe);
+        aim_hot_spots(&opts, &mut target, &original);
+        targets.push(target);
+    } else {
+        for &pid in &opts.pids {
+            // We can't mprotect another process's pages, but we don't need to: foreign writes go
+            // through the kernel, which can write through page protections on our behalf.
+            eprintln!("Targeting process {}, leaving its permissions alone", pid);
+            let mappings = get_memmap(pid)?;
+            for mapping in &mappings {
+                eprintln!("{}", mapping);
+            }
+            check_allowlist(&opts, &mappings, pid)?;
+            let shared = shared_with_self(&mappings)?;
+            if !shared.is_empty() {
+                eprintln!(
+                    "Warning: process {} shares {} mapping(s) with us, and a shot into one hits \
+                     our copy too:",
+                    pid,
+                    shared.len()
+                );
+                for mapping in shared {
+                    eprintln!("  {}", mapping);
+                }
+            }
+            let guards = find_guard_pages(&mappings, pid, &opts)?;
+            let mut candidates = mappings
+                .iter()
+                .filter(|m| is_candidate(m, &opts) && !guards.contains(&m.start_addr))
+                .cloned()
+                .collect();
+            if opts.target_tls {
+                keep_tls(&mut candidates, tls_regions(pid)?, pid)?;
+            }
+            if opts.resident_only {
+                keep_resident(&mut candidates, pid, pagesize)?;
+            }
+            print_residency(&candidates, pid, pagesize);
+            let mut target = TargetProcess::new(Some(pid), candidates, &heap_regions(&mappings));
+            if opts.min_rounds.is_some() {
+                target.safe = safe_indices(&target.candidates, &safe_mappings(&mappings, pid)?);
+            }
+            if opts.bundle.is_some() {
+                target.maps = mappings.clone();
+            }
+            aim_hot_spots(&opts, &mut target, &mappings);
+            targets.push(target);
+        }
+    }
+
+    if opts.min_rounds.is_some() && targets.iter().all(|t| t.safe.is_empty()) {
+        return Err(anyhow!(
+            "Found nothing safe enough to start --min-rounds on, try adding --add-scratch"
+        ));
+    }
+
+    let script = match &opts.script {
+        Some(path) => {
+            let text = fs::read_to_string(path)
+                .context(format!("Failed to read script {}", path.display()))?;
+            let lines =
+                parse_script(&text).context(format!("Invalid script {}", path.display()))?;
+            let mappings = get_memmap(opts.single_target()?)?;
+            Some(resolve_script(&lines, &mappings, &targets[0].candidates)?)
+        }
+        None => None,
+    };
+
+    if opts.demo && !opts.quiet {
+        eprintln!();
+        demo::introduce(&targets);
+    } else {
+        eprintln!(
+            "\nWelcome to Rust Roulette! It's a daring game where pages of memory are overwritten "
+        );
+        eprintln!("until something terrible happens!\n");
+        eprintln!("'Some of you may die, but it's a sacrifice I'm willing to make' - J. Lithgow\n");
+    }
+    let first_round = resumed.map_or(0, |state| state.rounds);
+    let exit = match play(
+        &opts,
+        &mut targets,
+        script.as_deref(),
+        pagesize,
+        &mut rng,
+        first_round,
+    ) {
+        Ok(()) if targets.iter().any(|t| !t.alive) => Exit::TargetDied,
+        Ok(()) => Exit::Clean,
+        Err(e) => {
+            eprintln!("Error: {:?}", e);
+            Exit::Error
+        }
+    };
 
     // Previously-used functionality where a NOP slide was written inside of libc's executable
     // mapping and then we jumped into it.
@@ -108,4 +1372,49 @@ fn main() -> Result<()> {
     //        func();
     //    }
     //}
+
+    Ok(exit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mapping(start: u64, end: u64, path: &str) -> Mapping {
+        Mapping::new(start, end, "rw-p".into(), 0, "00:00".into(), 0, path.into())
+    }
+
+    #[test]
+    fn candidates_follow_the_kind_filters() {
+        let mappings = [
+            mapping(0x1000, 0x2000, "/usr/bin/app"),
+            mapping(0x2000, 0x3000, ""),
+            mapping(0x3000, 0x4000, "[heap]"),
+            mapping(0x4000, 0x5000, "[stack]"),
+            mapping(0x5000, 0x6000, "[vdso]"),
+        ];
+        let candidates = |opts: &Options| -> Vec<&str> {
+            mappings
+                .iter()
+                .filter(|m| is_candidate(m, opts))
+                .map(|m| m.path.as_str())
+                .collect()
+        };
+        let mut opts = Options::default();
+        // By default everything [bracketed] sits out
+        assert_eq!(candidates(&opts), ["/usr/bin/app", ""]);
+
+        opts.skip_kinds = Some(vec![MappingKind::Stack, MappingKind::Vdso]);
+        assert_eq!(candidates(&opts), ["/usr/bin/app", "", "[heap]"]);
+        opts.skip_kinds = Some(vec![MappingKind::Data, MappingKind::Anonymous]);
+        assert_eq!(candidates(&opts), ["[heap]", "[stack]", "[vdso]"]);
+        // An empty list skips nothing at all
+        opts.skip_kinds = Some(Vec::new());
+        assert_eq!(candidates(&opts).len(), mappings.len());
+
+        opts.exclude_heap = true;
+        assert_eq!(candidates(&opts), ["/usr/bin/app", "", "[stack]", "[vdso]"]);
+        opts.only = Some("app".into());
+        assert_eq!(candidates(&opts), ["/usr/bin/app"]);
+    }
 }
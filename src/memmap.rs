@@ -1,17 +1,85 @@
+use crate::format::parse_mappings_json;
 use anyhow::{Context, Result, anyhow};
+use flate2::read::GzDecoder;
+use nix::errno::Errno;
 use nix::sys::mman::{ProtFlags, mprotect};
+use std::collections::HashMap;
 use std::ffi::c_void;
 use std::fmt::{Debug, Display, Formatter};
-use std::fs::File;
-use std::io::{BufRead, BufReader};
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, Read};
+use std::mem::ManuallyDrop;
+use std::os::fd::{FromRawFd, RawFd};
+use std::os::unix::fs::FileExt;
+use std::path::Path;
 use std::ptr::NonNull;
+use std::str::FromStr;
+use std::thread;
+use std::time::Duration;
 
 #[derive(Clone)]
 pub struct Mapping {
     pub start_addr: u64,
     pub end_addr: u64,
     pub permissions: PermissionSet,
+    pub offset: u64,
+    pub device: String,
+    pub inode: u64,
     pub path: String,
+    // The maps line this was parsed from, only kept when asked for
+    raw_line: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum MappingKind {
+    Code,
+    Data,
+    Heap,
+    Stack,
+    Anonymous,
+    // The kernel-provided regions: its code for fast syscalls, the data that code reads, and the
+    // legacy fixed-address syscall page
+    Vdso,
+    Vvar,
+    Vsyscall,
+    // Any other [bracketed] region, like [uprobes]
+    Special,
+}
+
+impl Display for MappingKind {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        let name = match self {
+            MappingKind::Code => "code",
+            MappingKind::Data => "data",
+            MappingKind::Heap => "heap",
+            MappingKind::Stack => "stack",
+            MappingKind::Anonymous => "anonymous",
+            MappingKind::Vdso => "vdso",
+            MappingKind::Vvar => "vvar",
+            MappingKind::Vsyscall => "vsyscall",
+            MappingKind::Special => "special",
+        };
+        f.pad(name)
+    }
+}
+
+impl FromStr for MappingKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<MappingKind> {
+        match s {
+            "code" => Ok(MappingKind::Code),
+            "data" => Ok(MappingKind::Data),
+            "heap" => Ok(MappingKind::Heap),
+            "stack" => Ok(MappingKind::Stack),
+            "anonymous" => Ok(MappingKind::Anonymous),
+            "vdso" => Ok(MappingKind::Vdso),
+            "vvar" => Ok(MappingKind::Vvar),
+            "vsyscall" => Ok(MappingKind::Vsyscall),
+            "special" => Ok(MappingKind::Special),
+            _ => Err(anyhow!("unknown mapping kind '{}'", s)),
+        }
+    }
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -19,6 +87,7 @@ pub struct PermissionSet {
     readable: bool,
     writeable: bool,
     executable: bool,
+    shared: bool,
 }
 
 impl PermissionSet {
@@ -27,36 +96,125 @@ impl PermissionSet {
             readable: false,
             writeable: false,
             executable: false,
+            shared: false,
         };
         for letter in s.chars() {
             match letter {
                 'r' => result.readable = true,
                 'w' => result.writeable = true,
                 'x' => result.executable = true,
+                's' => result.shared = true,
                 _ => continue,
             };
         }
         result
     }
 
+    // `PermissionSet::from` is taken by the string parser, so this spells out the ProtFlags one
+    pub fn from_prot_flags(flags: ProtFlags) -> PermissionSet {
+        flags.into()
+    }
+
+    pub fn is_readable(&self) -> bool {
+        self.readable
+    }
+
+    pub fn is_writable(&self) -> bool {
+        self.writeable
+    }
+
+    pub fn is_executable(&self) -> bool {
+        self.executable
+    }
+
+    pub fn is_shared(&self) -> bool {
+        self.shared
+    }
+
+    // The same as the is_* predicates above, under the names they were first given. New code
+    // should use is_*, which are what Mapping has too.
+    pub fn readable(&self) -> bool {
+        self.is_readable()
+    }
+
+    pub fn writeable(&self) -> bool {
+        self.is_writable()
+    }
+
+    pub fn executable(&self) -> bool {
+        self.is_executable()
+    }
+
+    pub fn shared(&self) -> bool {
+        self.is_shared()
+    }
+
+    pub fn with_executable(&self) -> PermissionSet {
+        PermissionSet {
+            executable: true,
+            ..self.clone()
+        }
+    }
+
+    // mprotect can't change whether a mapping is shared, so what to expect after one keeps the
+    // mapping's own
+    pub fn with_shared(&self, shared: bool) -> PermissionSet {
+        PermissionSet {
+            shared,
+            ..self.clone()
+        }
+    }
+
     pub fn and(&self, mask: &PermissionSet) -> bool {
-        return (self.readable && (self.readable == mask.readable))
+        (self.readable && (self.readable == mask.readable))
             || (self.writeable && (self.writeable == mask.writeable))
-            || (self.executable && (self.executable == mask.executable));
+            || (self.executable && (self.executable == mask.executable))
     }
 }
 
-impl Into<ProtFlags> for PermissionSet {
-    fn into(self) -> ProtFlags {
+// Unlike `PermissionSet::from`, which picks out whatever letters it knows, this insists on the
+// four-letter form that maps uses, e.g. "rw-p" or "r--s"
+impl FromStr for PermissionSet {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<PermissionSet> {
+        let valid = s.len() == 4
+            && s.chars()
+                .zip(["r-", "w-", "x-", "ps"])
+                .all(|(c, allowed)| allowed.contains(c));
+        if !valid {
+            return Err(anyhow!(
+                "invalid permissions '{}', expected four letters like 'rwxp' or 'r--s'",
+                s
+            ));
+        }
+        Ok(PermissionSet::from(s))
+    }
+}
+
+impl From<PermissionSet> for ProtFlags {
+    fn from(perms: PermissionSet) -> ProtFlags {
         let mut result = ProtFlags::empty();
-        result.set(ProtFlags::PROT_READ, self.readable);
-        result.set(ProtFlags::PROT_WRITE, self.writeable);
-        result.set(ProtFlags::PROT_EXEC, self.executable);
+        result.set(ProtFlags::PROT_READ, perms.readable);
+        result.set(ProtFlags::PROT_WRITE, perms.writeable);
+        result.set(ProtFlags::PROT_EXEC, perms.executable);
 
         result
     }
 }
 
+// ProtFlags has no notion of sharing, so the result is always private
+impl From<ProtFlags> for PermissionSet {
+    fn from(flags: ProtFlags) -> PermissionSet {
+        PermissionSet {
+            readable: flags.contains(ProtFlags::PROT_READ),
+            writeable: flags.contains(ProtFlags::PROT_WRITE),
+            executable: flags.contains(ProtFlags::PROT_EXEC),
+            shared: false,
+        }
+    }
+}
+
 impl Display for PermissionSet {
     fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
         if self.readable {
@@ -77,34 +235,237 @@ impl Display for PermissionSet {
             write!(f, "-")?;
         }
 
+        if self.shared {
+            write!(f, "s")?;
+        } else {
+            write!(f, "p")?;
+        }
+
         Ok(())
     }
 }
 
 impl Mapping {
-    pub fn new(start_addr: u64, end_addr: u64, permissions: String, path: String) -> Mapping {
+    pub fn new(
+        start_addr: u64,
+        end_addr: u64,
+        permissions: String,
+        offset: u64,
+        device: String,
+        inode: u64,
+        path: String,
+    ) -> Mapping {
         Mapping {
             start_addr,
             end_addr,
             permissions: PermissionSet::from(&permissions),
+            offset,
+            device,
+            inode,
             path,
+            raw_line: None,
         }
     }
 
-    // Get the size of the mapping in bytes
+    // The line of the maps file this was parsed from, if it was read with the raw lines kept
+    pub fn raw_line(&self) -> Option<&str> {
+        self.raw_line.as_deref()
+    }
+
+    // Get the size of the mapping in bytes. `end_addr` is exclusive, as in procfs. A reversed range
+    // has no size rather than wrapping around.
     pub fn size(&self) -> u64 {
-        return self.end_addr - self.start_addr;
+        self.end_addr.saturating_sub(self.start_addr)
+    }
+
+    pub fn is_readable(&self) -> bool {
+        self.permissions.is_readable()
+    }
+
+    pub fn is_writable(&self) -> bool {
+        self.permissions.is_writable()
+    }
+
+    pub fn is_executable(&self) -> bool {
+        self.permissions.is_executable()
+    }
+
+    /// Writeable and executable at once, which W^X is meant to rule out. JITs are the usual
+    /// legitimate reason, and anything else is worth a closer look.
+    ///
+    /// ```
+    /// use rust_roulette::memmap::Mapping;
+    ///
+    /// let mapping = |perms: &str| Mapping::new(0x1000, 0x2000, perms.into(), 0, "00:00".into(), 0, "".into());
+    /// assert!(mapping("rwxp").is_wx());
+    /// assert!(!mapping("r-xp").is_wx());
+    /// assert!(!mapping("rw-p").is_wx());
+    /// ```
+    pub fn is_wx(&self) -> bool {
+        self.is_writable() && self.is_executable()
+    }
+
+    // Whether the mapping comes from a file on disk, which is what a nonzero inode means
+    pub fn is_file_backed(&self) -> bool {
+        self.inode != 0
+    }
+
+    // Whether the file behind a file-backed mapping can no longer be opened under its path, either
+    // because procfs marks it "(deleted)" or because it's not there any more (an unmounted
+    // filesystem, a capture from another machine...). Anything that wants to read the file itself
+    // rather than the mapped copy should check this first.
+    pub fn backing_file_missing(&self) -> bool {
+        self.is_file_backed()
+            && (self.path.ends_with(" (deleted)") || fs::metadata(&self.path).is_err())
+    }
+
+    pub fn contains_addr(&self, addr: u64) -> bool {
+        addr >= self.start_addr && addr < self.end_addr
+    }
+
+    /// The two halves of the mapping either side of `addr`, like a partial mprotect would leave.
+    /// Both keep the permissions and path, and the second's offset moves along with its start.
+    /// `addr` has to be a boundary of `pagesize` pages strictly inside the mapping, or there's
+    /// nothing to split. The page size is the target's, which for a map from another system needn't
+    /// be ours.
+    ///
+    /// ```
+    /// use rust_roulette::memmap::Mapping;
+    ///
+    /// let mapping = Mapping::new(0x10000, 0x14000, "r-xp".into(), 0x2000, "fd:00".into(), 7, "/lib/libfoo.so".into());
+    /// let (low, high) = mapping.split_at(0x11000, 0x1000).unwrap();
+    /// assert_eq!((low.start_addr, low.end_addr, low.offset), (0x10000, 0x11000, 0x2000));
+    /// assert_eq!((high.start_addr, high.end_addr, high.offset), (0x11000, 0x14000, 0x3000));
+    /// assert_eq!((high.permissions, high.path.as_str()), (mapping.permissions.clone(), "/lib/libfoo.so"));
+    ///
+    /// // The ends, anything outside and anything between pages don't split it
+    /// assert!(mapping.split_at(0x10000, 0x1000).is_none());
+    /// assert!(mapping.split_at(0x14000, 0x1000).is_none());
+    /// assert!(mapping.split_at(0x20000, 0x1000).is_none());
+    /// assert!(mapping.split_at(0x11800, 0x1000).is_none());
+    ///
+    /// // With 16K pages, only a 16K boundary will do
+    /// let big = Mapping::new(0x10000, 0x20000, "rw-p".into(), 0, "00:00".into(), 0, "".into());
+    /// assert!(big.split_at(0x11000, 0x4000).is_none());
+    /// let (low, high) = big.split_at(0x14000, 0x4000).unwrap();
+    /// assert_eq!((low.end_addr, high.start_addr, high.offset), (0x14000, 0x14000, 0x4000));
+    /// ```
+    pub fn split_at(&self, addr: u64, pagesize: u64) -> Option<(Mapping, Mapping)> {
+        if addr <= self.start_addr || addr >= self.end_addr || !addr.is_multiple_of(pagesize) {
+            return None;
+        }
+        let half = |start_addr, end_addr| Mapping {
+            start_addr,
+            end_addr,
+            offset: self.offset + (start_addr - self.start_addr),
+            // Neither half is what the maps line said
+            raw_line: None,
+            ..self.clone()
+        };
+        Some((half(self.start_addr, addr), half(addr, self.end_addr)))
+    }
+
+    /// Whether the two mappings share at least one byte. Ranges are half-open, so mappings that
+    /// merely touch don't overlap.
+    ///
+    /// ```
+    /// use rust_roulette::memmap::Mapping;
+    ///
+    /// let mapping = |start, end| Mapping::new(start, end, "rw-p".into(), 0, "00:00".into(), 0, "".into());
+    /// assert!(mapping(0x1000, 0x3000).overlaps(&mapping(0x2000, 0x4000)));
+    /// assert!(!mapping(0x1000, 0x2000).overlaps(&mapping(0x2000, 0x3000)));
+    /// ```
+    pub fn overlaps(&self, other: &Mapping) -> bool {
+        self.start_addr < other.end_addr && other.start_addr < self.end_addr
+    }
+
+    // Where in the backing file the byte at `vaddr` came from, if it's inside this mapping
+    pub fn vaddr_to_file_offset(&self, vaddr: u64) -> Option<u64> {
+        if self.contains_addr(vaddr) {
+            Some(self.offset + (vaddr - self.start_addr))
+        } else {
+            None
+        }
+    }
+
+    // Where the byte at `file_offset` in the backing file is loaded, if this segment covers it
+    pub fn file_offset_to_vaddr(&self, file_offset: u64) -> Option<u64> {
+        if file_offset >= self.offset && file_offset - self.offset < self.size() {
+            Some(self.start_addr + (file_offset - self.offset))
+        } else {
+            None
+        }
+    }
+
+    pub fn kind(&self) -> MappingKind {
+        match self.path.as_str() {
+            "[heap]" => MappingKind::Heap,
+            "[stack]" => MappingKind::Stack,
+            "" => MappingKind::Anonymous,
+            _ if self.anon_name().is_some() => MappingKind::Anonymous,
+            "[vdso]" => MappingKind::Vdso,
+            // Newer kernels split the clock pages out into [vvar_vclock]
+            "[vvar]" | "[vvar_vclock]" => MappingKind::Vvar,
+            "[vsyscall]" => MappingKind::Vsyscall,
+            p if p.starts_with("[") => MappingKind::Special,
+            _ if self.is_executable() => MappingKind::Code,
+            _ => MappingKind::Data,
+        }
+    }
+
+    // The label of an anonymous mapping named with prctl(PR_SET_VMA_ANON_NAME), which procfs shows
+    // as "[anon:<name>]"
+    pub fn anon_name(&self) -> Option<&str> {
+        self.path.strip_prefix("[anon:")?.strip_suffix("]")
+    }
+
+    // The last component of the path, e.g. "libc.so.6" or "[heap]"
+    pub fn basename(&self) -> &str {
+        self.path.rsplit('/').next().unwrap_or(&self.path)
     }
 
     pub fn set_permissions(&mut self, new_perms: PermissionSet) -> Result<()> {
-        let ptr = NonNull::new(self.start_addr as *mut c_void).ok_or(anyhow!(format!(
-            "Failed to cast address 0x{:x} as it was null",
-            self.start_addr
-        )))?;
-        unsafe {
-            mprotect(ptr, self.size() as usize, new_perms.into())
-                .context(format!("Failed to set new page permissions on 0x{:x}", self.start_addr))
+        let ptr =
+            NonNull::new(local_addr(self.start_addr)? as *mut c_void).ok_or(anyhow!(format!(
+                "Failed to cast address 0x{:x} as it was null",
+                self.start_addr
+            )))?;
+        unsafe { mprotect(ptr, local_addr(self.size())?, new_perms.into()) }.map_err(|errno| {
+            anyhow!(errno).context(format!(
+                "Failed to set new page permissions on 0x{:x}: {}",
+                self.start_addr,
+                explain_mprotect_error(errno)
+            ))
+        })
+    }
+}
+
+/// What an mprotect failure means for the player, since the errno alone rarely says what to do
+/// about it.
+///
+/// ```
+/// use nix::errno::Errno;
+/// use rust_roulette::memmap::explain_mprotect_error;
+///
+/// assert!(explain_mprotect_error(Errno::EACCES).starts_with("permission denied"));
+/// assert!(explain_mprotect_error(Errno::EINVAL).contains("can't be remapped"));
+/// assert!(explain_mprotect_error(Errno::EAGAIN).contains("try again"));
+/// ```
+pub fn explain_mprotect_error(errno: Errno) -> &'static str {
+    match errno {
+        Errno::EACCES | Errno::EPERM => {
+            "permission denied (try as root, or check for a policy like SELinux or \
+             memory-deny-write-execute that refuses writeable code)"
         }
+        Errno::EINVAL => {
+            "invalid argument (the region can't be remapped, e.g. [vsyscall], or isn't page aligned)"
+        }
+        Errno::ENOMEM => {
+            "out of memory (part of the range was unmapped, or splitting it would pass the limit on \
+             mappings)"
+        }
+        Errno::EAGAIN | Errno::EINTR => "temporarily unavailable, try again",
+        _ => "unexpected error",
     }
 }
 
@@ -118,56 +479,1536 @@ impl Display for Mapping {
     }
 }
 
+// Convert an address (or length) in our own address space to a usize before it's cast to a
+// pointer. Mappings are parsed as u64 whatever the pointer width, so on a 32-bit build a value that
+// doesn't fit can only come from a 64-bit process's map, and an `as` cast would silently truncate
+// it to some other address of ours.
+pub fn local_addr(addr: u64) -> Result<usize> {
+    usize::try_from(addr).map_err(|_| {
+        anyhow!(
+            "0x{:x} doesn't fit in a {}-bit pointer, is it from a process of another architecture?",
+            addr,
+            usize::BITS
+        )
+    })
+}
+
+/// Read and parse `/proc/<pid>/maps`. Lines that fail to parse are reported on stderr and skipped.
+///
+/// ```
+/// use rust_roulette::memmap::get_memmap;
+///
+/// for mapping in get_memmap(std::process::id())? {
+///     if mapping.is_writable() && mapping.is_file_backed() {
+///         println!("{}", mapping);
+///     }
+/// }
+/// # Ok::<(), anyhow::Error>(())
+/// ```
 pub fn get_memmap(pid: u32) -> Result<Vec<Mapping>> {
-    let mut results: Vec<Mapping> = Vec::new();
+    let parsed = parse_memmap_consistent(|| {
+        let file = File::open(format!("/proc/{}/maps", pid))
+            .context("Failed to open process memory map in procfs")?;
+        Ok(BufReader::new(file))
+    })?;
+    for error in &parsed.errors {
+        eprintln!("{}", error);
+    }
+
+    Ok(parsed.mappings)
+}
+
+// Like get_memmap, but every mapping keeps the line it came from, for checking the parser against
+// what the kernel actually wrote
+pub fn get_memmap_with_raw_lines(pid: u32) -> Result<Vec<Mapping>> {
+    let path = format!("/proc/{}/maps", pid);
+    let file = File::open(&path).context("Failed to open process memory map in procfs")?;
+    let parsed = parse_memmap_with_raw_lines(BufReader::new(file))?;
+    for error in &parsed.errors {
+        eprintln!("{}", error);
+    }
+
+    Ok(parsed.mappings)
+}
+
+/// Every process procfs shows us, in pid order. Processes come and go while anyone looks, so some
+/// of these may be gone by the time they're read.
+///
+/// ```
+/// use rust_roulette::memmap::all_pids;
+///
+/// assert!(all_pids().contains(&std::process::id()));
+/// ```
+pub fn all_pids() -> Vec<u32> {
+    let Ok(entries) = fs::read_dir("/proc") else {
+        return Vec::new();
+    };
+    let mut pids: Vec<u32> = entries
+        .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse().ok())
+        .collect();
+    pids.sort();
+    pids
+}
+
+/// Read and parse a maps file through a descriptor that someone else already has open, for when it
+/// can't be reopened by path. The descriptor is only borrowed and stays open. Reading starts from
+/// its current offset and leaves it at the end, so pass a fresh one (or seek it back to 0).
+///
+/// ```
+/// use rust_roulette::memmap::get_memmap_from_fd;
+/// use std::os::fd::AsRawFd;
+///
+/// let maps = std::fs::File::open("/proc/self/maps")?;
+/// assert!(!get_memmap_from_fd(maps.as_raw_fd())?.is_empty());
+/// // Still ours, and still open
+/// maps.metadata()?;
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+pub fn get_memmap_from_fd(fd: RawFd) -> Result<Vec<Mapping>> {
+    // Dropping a File closes its descriptor, which isn't ours to close
+    let file = ManuallyDrop::new(unsafe { File::from_raw_fd(fd) });
+    let parsed = parse_memmap(BufReader::new(&*file))
+        .context(format!("Failed to read a memory map from fd {}", fd))?;
+    for error in &parsed.errors {
+        eprintln!("{}", error);
+    }
+
+    Ok(parsed.mappings)
+}
+
+const MAPS_READ_ATTEMPTS: u32 = 3;
+const MAPS_RETRY_BACKOFF: Duration = Duration::from_millis(10);
 
-    let file = File::open(format!("/proc/{}/maps", pid))
-        .context("Failed to open process memory map in procfs")?;
-    let reader = BufReader::new(file);
+/// Parse a maps file that may be changing under us, reading it again (after a short, growing
+/// pause) while what comes back is out of order or overlapping. The kernel builds maps a page at a
+/// time, so a process that maps and unmaps quickly can be caught halfway. If no read comes back
+/// clean, the last one is used anyway, with a warning, less the lines that overlapped.
+///
+/// ```
+/// use rust_roulette::memmap::parse_memmap_consistent;
+///
+/// let torn = "00400000-00402000 r--p 0 fd:00 1 /a\n00401000-00403000 r--p 0 fd:00 1 /b\n";
+/// let clean = "00400000-00401000 r--p 0 fd:00 1 /a\n00401000-00403000 r--p 0 fd:00 1 /b\n";
+/// let mut reads = vec![torn, clean].into_iter();
+/// let parsed = parse_memmap_consistent(|| Ok(reads.next().unwrap().as_bytes()))?;
+/// assert_eq!(parsed.mappings[0].end_addr, 0x401000);
+/// assert_eq!((parsed.mappings.len(), parsed.overlaps), (2, 0));
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+pub fn parse_memmap_consistent<R: BufRead>(
+    mut open: impl FnMut() -> Result<R>,
+) -> Result<ParsedMemmap> {
+    let mut backoff = MAPS_RETRY_BACKOFF;
+    let mut attempt = 1;
+    loop {
+        let parsed = parse_memmap(open()?)?;
+        if parsed.overlaps == 0 {
+            return Ok(parsed);
+        }
+        if attempt == MAPS_READ_ATTEMPTS {
+            eprintln!(
+                "Warning: no consistent snapshot of the memory map after {} reads, skipping {} \
+                 overlapping line(s)",
+                attempt, parsed.overlaps
+            );
+            return Ok(parsed);
+        }
+        thread::sleep(backoff);
+        backoff *= 2;
+        attempt += 1;
+    }
+}
+
+// Read a saved copy of a maps file, e.g. one captured from another machine. Files ending in .gz are
+// decompressed on the way in. JSON dumps written by `--list --format json` are recognised by their leading '['. A file is
+// only read once, so the raw lines of a text one are kept for `--list -vv`.
+// Tarballs (.tar, .tar.gz, .tgz) and .zip archives of a /proc/PID directory work too, as long as
+// there's an entry called `maps` somewhere inside; anything else in there, like smaps, is ignored.
+pub fn load_memmap(path: &Path) -> Result<Vec<Mapping>> {
+    let name = path.to_string_lossy();
+    let bytes = if name.ends_with(".tar") {
+        let file = File::open(path).context(format!("Failed to open {}", path.display()))?;
+        read_tar_maps(file, path)?
+    } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+        let file = File::open(path).context(format!("Failed to open {}", path.display()))?;
+        read_tar_maps(GzDecoder::new(file), path)?
+    } else if name.ends_with(".zip") {
+        let file = File::open(path).context(format!("Failed to open {}", path.display()))?;
+        read_zip_maps(file, path)?
+    } else if name.ends_with(".gz") {
+        let file = File::open(path).context(format!("Failed to open {}", path.display()))?;
+        let mut bytes = Vec::new();
+        GzDecoder::new(file)
+            .read_to_end(&mut bytes)
+            .context(format!("Failed to decompress {}", path.display()))?;
+        bytes
+    } else {
+        fs::read(path).context(format!("Failed to read maps file {}", path.display()))?
+    };
+
+    // A `--list --format json` dump rather than procfs text
+    if bytes.trim_ascii_start().starts_with(b"[") {
+        let text = String::from_utf8(bytes).context(format!("{} isn't UTF-8", path.display()))?;
+        let parsed =
+            parse_mappings_json(&text).context(format!("Invalid JSON in {}", path.display()))?;
+        for error in &parsed.errors {
+            eprintln!("{}", error);
+        }
+        return Ok(parsed.mappings);
+    }
+    let parsed = parse_memmap_with_raw_lines(&bytes[..])?;
+    for error in &parsed.errors {
+        eprintln!("{}", error);
+    }
+
+    Ok(parsed.mappings)
+}
+
+// How deep in an archive an entry is, if it's a maps file. The shallowest one wins, in case the
+// archive also has e.g. task/<tid>/maps.
+fn maps_entry_depth(entry: &str) -> Option<usize> {
+    (entry.rsplit('/').next() == Some("maps")).then(|| entry.matches('/').count())
+}
 
-    for line in reader.lines() {
+// The maps file out of a tarball. Entries can only be read in order, so every maps file on the way
+// is read and the shallowest one kept.
+fn read_tar_maps(reader: impl Read, path: &Path) -> Result<Vec<u8>> {
+    let context = || format!("Failed to read the tarball {}", path.display());
+    let mut archive = tar::Archive::new(reader);
+    let mut best: Option<(usize, Vec<u8>)> = None;
+    for entry in archive.entries().with_context(context)? {
+        let mut entry = entry.with_context(context)?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let entry_path = entry.path().with_context(context)?;
+        let Some(depth) = maps_entry_depth(&entry_path.to_string_lossy()) else {
+            continue;
+        };
+        if best
+            .as_ref()
+            .is_some_and(|(best_depth, _)| *best_depth <= depth)
+        {
+            continue;
+        }
+        let mut bytes = Vec::new();
+        entry.read_to_end(&mut bytes).with_context(context)?;
+        best = Some((depth, bytes));
+    }
+    best.map(|(_, bytes)| bytes)
+        .ok_or(anyhow!("No maps file in {}", path.display()))
+}
+
+// The maps file out of a zip archive
+fn read_zip_maps(file: File, path: &Path) -> Result<Vec<u8>> {
+    let context = || format!("Failed to read the zip archive {}", path.display());
+    let mut archive = zip::ZipArchive::new(file).with_context(context)?;
+    let entry = archive
+        .file_names()
+        .filter_map(|name| {
+            let name = name.ok()?;
+            Some((maps_entry_depth(&name)?, name.into_owned()))
+        })
+        .min()
+        .map(|(_, name)| name)
+        .ok_or(anyhow!("No maps file in {}", path.display()))?;
+    let mut bytes = Vec::new();
+    archive
+        .by_name(&entry)
+        .with_context(context)?
+        .read_to_end(&mut bytes)
+        .with_context(context)?;
+    Ok(bytes)
+}
+
+pub struct ParsedMemmap {
+    // In address order, with no two overlapping
+    pub mappings: Vec<Mapping>,
+    // One message per line that was skipped because it couldn't be parsed
+    pub errors: Vec<String>,
+    // How many of those lines parsed fine but started before the end of the mapping above them,
+    // which in a live map is a sign of catching the kernel halfway through a change
+    pub overlaps: usize,
+}
+
+impl ParsedMemmap {
+    pub(crate) fn new() -> ParsedMemmap {
+        ParsedMemmap {
+            mappings: Vec::new(),
+            errors: Vec::new(),
+            overlaps: 0,
+        }
+    }
+
+    // Keep `mapping` unless it starts before the last one kept ends, in which case it's dropped
+    // and reported under `label` instead
+    pub(crate) fn push(&mut self, mapping: Mapping, label: &str) {
+        match self.mappings.last() {
+            Some(prev) if mapping.start_addr < prev.end_addr => {
+                self.overlaps += 1;
+                self.errors.push(format!(
+                    "{}: 0x{:x}-0x{:x} starts before the mapping above it ends, at 0x{:x}",
+                    label, mapping.start_addr, mapping.end_addr, prev.end_addr
+                ));
+            }
+            _ => self.mappings.push(mapping),
+        }
+    }
+}
+
+// Parse the contents of a maps file. Malformed lines are skipped and recorded in `errors` rather
+// than failing the whole parse; only I/O errors are fatal. A line that would leave the mappings
+// out of order or overlapping is dropped and reported in `errors` in the same way, so every caller
+// can rely on them being neither.
+pub fn parse_memmap<R: BufRead>(reader: R) -> Result<ParsedMemmap> {
+    parse_lines(reader, false)
+}
+
+/// Like [`parse_memmap`], but each mapping also keeps a copy of its line, for
+/// [`Mapping::raw_line`]. That doubles the memory a map takes, so it's only for debugging.
+///
+/// ```
+/// use rust_roulette::memmap::parse_memmap_with_raw_lines;
+///
+/// let line = "00400000-00401000 r-xp 00000000 fd:00 12   /bin/two  spaces";
+/// let parsed = parse_memmap_with_raw_lines(line.as_bytes())?;
+/// assert_eq!(parsed.mappings[0].raw_line(), Some(line));
+/// assert_eq!(parsed.mappings[0].path, "/bin/two  spaces");
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+pub fn parse_memmap_with_raw_lines<R: BufRead>(reader: R) -> Result<ParsedMemmap> {
+    parse_lines(reader, true)
+}
+
+fn parse_lines<R: BufRead>(reader: R, keep_raw: bool) -> Result<ParsedMemmap> {
+    let mut parsed = ParsedMemmap::new();
+
+    for (i, line) in reader.lines().enumerate() {
         let line = match line {
             Err(e) => {
                 return Err(e).context("Failed to read line in procfs maps file");
             }
             Ok(line) => line,
         };
-        let fields: Vec<&str> = line.split_whitespace().collect();
-        if fields.len() < 5 {
-            println!(
-                "Failed to parse bad maps entry because only {} fields were found: '{}'",
-                fields.len(),
-                line
+        match Mapping::try_from(line.as_str()) {
+            Ok(mut mapping) => {
+                if keep_raw {
+                    mapping.raw_line = Some(line);
+                }
+                parsed.push(mapping, &format!("line {}", i + 1))
+            }
+            Err(e) => parsed.errors.push(format!("line {}: {}", i + 1, e)),
+        }
+    }
+
+    Ok(parsed)
+}
+
+/// Why a line of a maps file didn't parse, naming the field that's wrong
+#[derive(Debug, PartialEq)]
+pub enum LineError {
+    TooFewFields { found: usize, line: String },
+    Address(String),
+    ReversedAddress(String),
+    Permissions(String),
+    Offset(String),
+    Device(String),
+    Inode { field: String, reason: String },
+}
+
+impl Display for LineError {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match self {
+            LineError::TooFewFields { found, line } => {
+                write!(f, "expected at least 5 fields, found {}: '{}'", found, line)
+            }
+            LineError::Address(field) => write!(
+                f,
+                "address field '{}' isn't two hex numbers separated by '-'",
+                field
+            ),
+            LineError::ReversedAddress(field) => {
+                write!(f, "address field '{}' ends below where it starts", field)
+            }
+            LineError::Permissions(field) => {
+                write!(
+                    f,
+                    "permissions field '{}' isn't like 'rwxp' or 'r--s'",
+                    field
+                )
+            }
+            LineError::Offset(field) => write!(f, "offset field '{}' isn't valid hex", field),
+            LineError::Device(field) => {
+                write!(f, "device field '{}' isn't major:minor in hex", field)
+            }
+            LineError::Inode { field, reason } => {
+                write!(f, "inode field '{}' isn't a number: {}", field, reason)
+            }
+        }
+    }
+}
+
+impl std::error::Error for LineError {}
+
+/// Parse a single line of a maps file, on its own.
+///
+/// ```
+/// use rust_roulette::memmap::{LineError, Mapping};
+///
+/// let file = Mapping::try_from("7f2c1000-7f2c3000 r-xp 00001000 fd:01 4242   /usr/lib/libc.so.6")?;
+/// assert_eq!((file.start_addr, file.size(), file.offset), (0x7f2c1000, 0x2000, 0x1000));
+/// assert_eq!((file.inode, file.path.as_str()), (4242, "/usr/lib/libc.so.6"));
+///
+/// let anon = Mapping::try_from("7f2c3000-7f2c4000 rw-p 00000000 00:00 0 ")?;
+/// assert!(anon.path.is_empty() && anon.is_writable());
+///
+/// let bad = Mapping::try_from("7f2c3000-7f2c4000 rw-p 00000000 00:00 zero");
+/// assert!(matches!(bad, Err(LineError::Inode { .. })));
+/// # Ok::<(), LineError>(())
+/// ```
+impl TryFrom<&str> for Mapping {
+    type Error = LineError;
+
+    fn try_from(line: &str) -> std::result::Result<Mapping, LineError> {
+        // The first five fields are split on any run of spaces or tabs, but the path is everything
+        // after them, kept byte for byte. Splitting it too would collapse runs of spaces inside it.
+        let mut fields = Vec::new();
+        let mut rest = line;
+        while fields.len() < 5 {
+            rest = rest.trim_start();
+            if rest.is_empty() {
+                return Err(LineError::TooFewFields {
+                    found: fields.len(),
+                    line: line.to_string(),
+                });
+            }
+            let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+            fields.push(&rest[..end]);
+            rest = &rest[end..];
+        }
+
+        let hex = |s: &str| u64::from_str_radix(s, 16).ok();
+        let (start, end) = fields[0]
+            .split_once('-')
+            .and_then(|(start, end)| Some((hex(start)?, hex(end)?)))
+            .ok_or(LineError::Address(fields[0].to_string()))?;
+        if end < start {
+            return Err(LineError::ReversedAddress(fields[0].to_string()));
+        }
+
+        let perms = fields[1];
+        if perms.parse::<PermissionSet>().is_err() {
+            return Err(LineError::Permissions(perms.to_string()));
+        }
+
+        let offset = hex(fields[2]).ok_or(LineError::Offset(fields[2].to_string()))?;
+
+        let device = fields[3];
+        if !device
+            .split_once(':')
+            .is_some_and(|(major, minor)| hex(major).is_some() && hex(minor).is_some())
+        {
+            return Err(LineError::Device(device.to_string()));
+        }
+
+        let inode = fields[4].parse::<u64>().map_err(|e| LineError::Inode {
+            field: fields[4].to_string(),
+            reason: e.to_string(),
+        })?;
+        let path = rest.trim_start();
+
+        Ok(Mapping::new(
+            start,
+            end,
+            perms.to_string(),
+            offset,
+            device.to_string(),
+            inode,
+            path.to_string(),
+        ))
+    }
+}
+
+pub enum MapChange {
+    Added(Mapping),
+    Removed(Mapping),
+    Changed { old: Mapping, new: Mapping },
+}
+
+impl Display for MapChange {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match self {
+            MapChange::Added(m) => write!(f, "+ {}", m),
+            MapChange::Removed(m) => write!(f, "- {}", m),
+            MapChange::Changed { old, new } => {
+                write!(f, "~ {} ({} -> {})", new, old.permissions, new.permissions)
+            }
+        }
+    }
+}
+
+// glibc aligns the heaps of its extra arenas to HEAP_MAX_SIZE, which is 64 MiB on 64-bit systems
+const ARENA_ALIGNMENT: u64 = 64 << 20;
+
+// The mappings that are probably malloc heap: [heap] itself, plus anonymous rw-p mappings starting
+// on a 64 MiB boundary, which is where glibc puts the extra arenas it creates for other threads.
+// This is only a heuristic. Other allocators and large aligned buffers fool it, and it can't see
+// big allocations that malloc hands straight to mmap.
+pub fn heap_regions(mappings: &[Mapping]) -> Vec<&Mapping> {
+    mappings
+        .iter()
+        .filter(|m| {
+            m.kind() == MappingKind::Heap
+                || (m.path.is_empty()
+                    && m.permissions == PermissionSet::from("rw-p")
+                    && m.start_addr % ARENA_ALIGNMENT == 0)
+        })
+        .collect()
+}
+
+// Bit 63 of a pagemap entry says the page is in RAM, rather than swapped out or never touched
+const PAGEMAP_PRESENT: u64 = 1 << 63;
+
+// The indices of a mapping's pages that are resident right now, from /proc/PID/pagemap, which has
+// one 64-bit entry per virtual page. Reading another process's pagemap takes the same access as
+// ptrace. Without CAP_SYS_ADMIN the frame numbers read as zero, but the present bit is still
+// there, which is all this needs. Pages past the end of what the kernel will describe, like the
+// vsyscall page above the user address space, count as not resident.
+pub fn resident_pages(pid: u32, mapping: &Mapping, pagesize: u64) -> Result<Vec<u64>> {
+    let path = format!("/proc/{}/pagemap", pid);
+    let file = File::open(&path).context(format!(
+        "Failed to open {} (it needs ptrace access to the process)",
+        path
+    ))?;
+    let pages = mapping.size() / pagesize;
+    let mut entries = vec![0u8; local_addr(pages * 8)?];
+    let read = file
+        .read_at(&mut entries, mapping.start_addr / pagesize * 8)
+        .context(format!("Failed to read {}", path))?;
+    Ok(entries[..read - read % 8]
+        .chunks_exact(8)
+        .enumerate()
+        .filter(|(_, entry)| {
+            u64::from_le_bytes((*entry).try_into().unwrap()) & PAGEMAP_PRESENT != 0
+        })
+        .map(|(idx, _)| idx as u64)
+        .collect())
+}
+
+// The fraction of a mapping's pages that are in RAM. Our own are asked about with mincore, in units
+// of the real page size whatever `pagesize` says, since that's what it fills its vector in.
+// Anyone else's go through pagemap, as in resident_pages.
+pub fn resident_ratio(pid: u32, mapping: &Mapping, pagesize: u64) -> Result<f64> {
+    if pid != std::process::id() {
+        let pages = mapping.size() / pagesize;
+        let resident = resident_pages(pid, mapping, pagesize)?.len();
+        return Ok(if pages == 0 {
+            0.0
+        } else {
+            resident as f64 / pages as f64
+        });
+    }
+    let real_pagesize = detect_page_size(None)?;
+    let mut pages = vec![0u8; local_addr(mapping.size().div_ceil(real_pagesize))?];
+    if pages.is_empty() {
+        return Ok(0.0);
+    }
+    let ret = unsafe {
+        nix::libc::mincore(
+            local_addr(mapping.start_addr)? as *mut c_void,
+            local_addr(mapping.size())?,
+            pages.as_mut_ptr(),
+        )
+    };
+    if ret != 0 {
+        return Err(std::io::Error::last_os_error())
+            .context(format!("mincore failed on 0x{:x}", mapping.start_addr));
+    }
+    // Only the lowest bit means anything
+    let resident = pages.iter().filter(|&&page| page & 1 != 0).count();
+    Ok(resident as f64 / pages.len() as f64)
+}
+
+/// Roughly where a process's argv and environment live, as a range of addresses. maps doesn't
+/// delimit them, but the kernel lays them out the same way for every program: the strings at the
+/// very top of the main thread's [stack], and below them the auxiliary vector and the argv and envp
+/// pointer arrays, starting right where the initial stack pointer was. /proc/PID/stat has that
+/// pointer (startstack) and where the environment strings end (env_end), so the range runs from
+/// one to the other. It's a slight overestimate, since it takes in the padding between the pieces.
+///
+/// ```
+/// use rust_roulette::memmap::{MappingKind, argv_env_region, get_memmap};
+///
+/// let (start, end) = argv_env_region(std::process::id())?;
+/// let mappings = get_memmap(std::process::id())?;
+/// let stack = mappings.iter().find(|m| m.kind() == MappingKind::Stack).unwrap();
+/// assert!(stack.start_addr < start && start < end && end <= stack.end_addr);
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+pub fn argv_env_region(pid: u32) -> Result<(u64, u64)> {
+    let path = format!("/proc/{}/stat", pid);
+    let stat = fs::read_to_string(&path).context(format!("Failed to read {}", path))?;
+    // The name in parentheses can have anything in it, spaces and parentheses included, so fields
+    // are counted from the last closing one, after which comes the third
+    let fields: Vec<&str> = stat
+        .rsplit_once(')')
+        .map(|(_, rest)| rest.split_whitespace().collect())
+        .unwrap_or_default();
+    let field = |n: usize| -> Result<u64> {
+        let value = fields
+            .get(n - 3)
+            .ok_or(anyhow!("{} has no field {}", path, n))?;
+        value
+            .parse()
+            .context(format!("Field {} of {} isn't a number", n, path))
+    };
+    let (start_stack, env_end) = (field(28)?, field(51)?);
+    // Both read as 0 for a process we aren't allowed to look into this closely
+    if start_stack == 0 || env_end <= start_stack {
+        return Err(anyhow!(
+            "{} doesn't say where argv and the environment are",
+            path
+        ));
+    }
+    Ok((start_stack, env_end))
+}
+
+/// How much of the address space the kernel randomizes, going by kernel.randomize_va_space
+///
+/// ```
+/// use rust_roulette::memmap::AslrState;
+///
+/// assert_eq!("0\n".parse::<AslrState>()?, AslrState::Off);
+/// assert_eq!("1\n".parse::<AslrState>()?, AslrState::Partial);
+/// assert_eq!("2\n".parse::<AslrState>()?, AslrState::Full);
+/// assert!("3".parse::<AslrState>().is_err());
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AslrState {
+    Off,
+    // Stacks, mmap and the vDSO move, but the heap starts right after the program
+    Partial,
+    Full,
+}
+
+impl FromStr for AslrState {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<AslrState> {
+        match s.trim() {
+            "0" => Ok(AslrState::Off),
+            "1" => Ok(AslrState::Partial),
+            "2" => Ok(AslrState::Full),
+            other => Err(anyhow!("unknown randomize_va_space setting '{}'", other)),
+        }
+    }
+}
+
+impl Display for AslrState {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        f.write_str(match self {
+            AslrState::Off => {
+                "ASLR is off, so every run lays memory out the same and a seed repeats a game \
+                 exactly, even across reboots"
+            }
+            AslrState::Partial => {
+                "ASLR is partial: stacks, libraries and the vDSO move between runs, but the heap \
+                 doesn't"
+            }
+            AslrState::Full => "ASLR is on, so addresses move between runs whatever the seed",
+        })
+    }
+}
+
+// Read the system's ASLR setting. Only Linux has the sysctl, and a container may hide it.
+pub fn aslr_state() -> Result<AslrState> {
+    let path = "/proc/sys/kernel/randomize_va_space";
+    fs::read_to_string(path)
+        .context(format!("Failed to read {}", path))?
+        .parse()
+        .context(format!("Failed to parse {}", path))
+}
+
+// The page size to assume when the system won't say: 16K on Apple Silicon, and 4K nearly
+// everywhere else
+pub const DEFAULT_PAGE_SIZE: u64 = if cfg!(all(target_vendor = "apple", target_arch = "aarch64")) {
+    16384
+} else {
+    4096
+};
+
+// The page size to go by: the override if there is one, like --page-size for a map from another
+// system, or else whatever sysconf says
+pub fn detect_page_size(page_size_override: Option<u64>) -> Result<u64> {
+    let system = match nix::unistd::sysconf(nix::unistd::SysconfVar::PAGE_SIZE) {
+        Ok(Some(size)) if size > 0 => Some(size as u64),
+        _ => None,
+    };
+    choose_page_size(page_size_override, system)
+}
+
+/// Settle on a page size from an override and what the system said, if anything, falling back on
+/// DEFAULT_PAGE_SIZE with a warning when there's neither. All of the page maths assumes a power of
+/// two, so anything else is an error, wherever it came from.
+///
+/// ```
+/// use rust_roulette::memmap::{DEFAULT_PAGE_SIZE, choose_page_size};
+///
+/// assert_eq!(choose_page_size(Some(65536), Some(4096))?, 65536);
+/// assert_eq!(choose_page_size(Some(16384), None)?, 16384);
+/// assert_eq!(choose_page_size(None, Some(4096))?, 4096);
+/// assert_eq!(choose_page_size(None, None)?, DEFAULT_PAGE_SIZE);
+/// assert!(choose_page_size(Some(3000), Some(4096)).is_err());
+/// assert!(choose_page_size(None, Some(0)).is_err());
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+pub fn choose_page_size(page_size_override: Option<u64>, system: Option<u64>) -> Result<u64> {
+    let pagesize = match (page_size_override, system) {
+        (Some(size), _) | (None, Some(size)) => size,
+        (None, None) => {
+            eprintln!(
+                "Warning: the system won't say what its page size is, so assuming {} bytes",
+                DEFAULT_PAGE_SIZE
             );
+            DEFAULT_PAGE_SIZE
+        }
+    };
+    if !pagesize.is_power_of_two() {
+        return Err(anyhow!(
+            "Page size {} isn't a nonzero power of two",
+            pagesize
+        ));
+    }
+    Ok(pagesize)
+}
+
+// The stacks of a process's threads: [stack] for the main thread, plus whichever mapping holds each
+// other thread's stack pointer. The pointers come from /proc/PID/task/TID/syscall, which only has
+// them for threads blocked in a syscall, so a thread that is running right now is missed.
+pub fn thread_stacks(pid: u32) -> Result<Vec<Mapping>> {
+    let mappings = get_memmap(pid)?;
+    let tasks = fs::read_dir(format!("/proc/{}/task", pid))
+        .context(format!("Failed to list the threads of process {}", pid))?;
+    let mut stacks: Vec<Mapping> = mappings
+        .iter()
+        .filter(|m| m.kind() == MappingKind::Stack)
+        .cloned()
+        .collect();
+    for task in tasks {
+        let task = task.context(format!("Failed to list the threads of process {}", pid))?;
+        // Threads can exit while we look, and a running one just says "running"
+        let Ok(syscall) = fs::read_to_string(task.path().join("syscall")) else {
             continue;
+        };
+        let fields: Vec<&str> = syscall.split_whitespace().collect();
+        let Some(sp) = fields
+            .len()
+            .checked_sub(2)
+            .and_then(|i| u64::from_str_radix(fields[i].strip_prefix("0x")?, 16).ok())
+        else {
+            continue;
+        };
+        if let Some(m) = mappings.iter().find(|m| m.contains_addr(sp))
+            && !stacks.iter().any(|s| s.start_addr == m.start_addr)
+        {
+            stacks.push(m.clone());
+        }
+    }
+    stacks.sort_by_key(|m| m.start_addr);
+    Ok(stacks)
+}
+
+// The guard pages below the given stacks: inaccessible anonymous ---p mappings ending right where a
+// stack starts, which the threading library leaves there so that an overflow faults rather than
+// running into whatever is mapped below. The main [stack] gets a guard gap from the kernel instead,
+// which isn't a mapping, so it usually has none.
+pub fn guard_pages<'a>(mappings: &'a [Mapping], stacks: &[Mapping]) -> Vec<&'a Mapping> {
+    mappings
+        .iter()
+        .filter(|m| {
+            m.path.is_empty()
+                && m.inode == 0
+                && m.permissions == PermissionSet::from("---p")
+                && stacks.iter().any(|s| s.start_addr == m.end_addr)
+        })
+        .collect()
+}
+
+/// Where the byte at `foff` in the file `basename` is loaded. Each segment of a file is mapped from
+/// its own offset, so this is whichever segment's `[offset, offset + size)` covers `foff`, or None
+/// if none does, like for a part of the file the loader never maps.
+///
+/// ```
+/// use rust_roulette::memmap::{Mapping, resolve_file_offset};
+///
+/// let segment = |start: u64, end: u64, perms: &str, offset| {
+///     Mapping::new(start, end, perms.into(), offset, "fd:00".into(), 7, "/lib/libfoo.so.1".into())
+/// };
+/// let mappings = vec![
+///     segment(0x7000_0000, 0x7000_1000, "r--p", 0),
+///     segment(0x7000_5000, 0x7000_7000, "r-xp", 0x1000),
+/// ];
+/// assert_eq!(resolve_file_offset(&mappings, "libfoo.so.1", 0x10), Some(0x7000_0010));
+/// assert_eq!(resolve_file_offset(&mappings, "libfoo.so.1", 0x1000), Some(0x7000_5000));
+/// assert_eq!(resolve_file_offset(&mappings, "libfoo.so.1", 0x2fff), Some(0x7000_6fff));
+/// assert_eq!(resolve_file_offset(&mappings, "libfoo.so.1", 0x3000), None);
+/// assert_eq!(resolve_file_offset(&mappings, "libbar.so.1", 0x10), None);
+/// ```
+pub fn resolve_file_offset(mappings: &[Mapping], basename: &str, foff: u64) -> Option<u64> {
+    mappings
+        .iter()
+        .filter(|m| m.basename() == basename)
+        .find_map(|m| m.file_offset_to_vaddr(foff))
+}
+
+/// The address `offset` bytes into every mapping of `basename`, for the ones big enough to have
+/// one. Each segment of a file is counted from its own start, so this is the same spot relative to
+/// every segment rather than one spot in the file.
+///
+/// ```
+/// use rust_roulette::memmap::{Mapping, segment_offsets};
+///
+/// let segment = |start: u64, end: u64, perms: &str, path: &str| {
+///     Mapping::new(start, end, perms.into(), 0, "fd:00".into(), 7, path.into())
+/// };
+/// let mappings = vec![
+///     segment(0x10000, 0x11000, "r--p", "/lib/libfoo.so.1"),
+///     segment(0x11000, 0x14000, "r-xp", "/lib/libfoo.so.1"),
+///     segment(0x14000, 0x14800, "rw-p", "/lib/libfoo.so.1"),
+///     segment(0x20000, 0x30000, "r-xp", "/lib/libbar.so.1"),
+/// ];
+/// let addrs: Vec<u64> = segment_offsets(&mappings, "libfoo.so.1", 0x900)
+///     .into_iter()
+///     .map(|(_, addr)| addr)
+///     .collect();
+/// assert_eq!(addrs, [0x10900, 0x11900]);
+/// assert_eq!(segment_offsets(&mappings, "libfoo.so.1", 0x200).len(), 3);
+/// assert!(segment_offsets(&mappings, "libfoo.so.1", 0x3000).is_empty());
+/// ```
+pub fn segment_offsets<'a>(
+    mappings: &'a [Mapping],
+    basename: &str,
+    offset: u64,
+) -> Vec<(&'a Mapping, u64)> {
+    mappings
+        .iter()
+        .filter(|m| m.basename() == basename && offset < m.size())
+        .map(|m| (m, m.start_addr + offset))
+        .collect()
+}
+
+/// The mappings that make up the program `exe`: every one of the file, matched by path so it works
+/// wherever the loader put them, plus an anonymous mapping starting right where the last one ends,
+/// which is where .bss goes once it outgrows the file's last page. A PIE executable lands at a
+/// random base and a non-PIE one at the address it was linked for, and both look the same here.
+///
+/// ```
+/// use rust_roulette::memmap::{Mapping, program_segments};
+///
+/// let layout = |base: u64| {
+///     let file = |start: u64, end: u64, perms: &str, offset| {
+///         Mapping::new(base + start, base + end, perms.into(), offset, "fd:00".into(), 7, "/bin/app".into())
+///     };
+///     vec![
+///         file(0, 0x1000, "r--p", 0),
+///         file(0x1000, 0x3000, "r-xp", 0x1000),
+///         file(0x3000, 0x4000, "rw-p", 0x3000),
+///         Mapping::new(base + 0x4000, base + 0x6000, "rw-p".into(), 0, "00:00".into(), 0, "".into()),
+///         Mapping::new(base + 0x9000, base + 0xa000, "rw-p".into(), 0, "00:00".into(), 0, "".into()),
+///     ]
+/// };
+/// // PIE, at a randomised base
+/// assert_eq!(program_segments(&layout(0x55d0_4f2a_1000), "/bin/app").len(), 4);
+/// // Non-PIE, where it was linked
+/// assert_eq!(program_segments(&layout(0x40_0000), "/bin/app").len(), 4);
+/// ```
+pub fn program_segments<'a>(mappings: &'a [Mapping], exe: &str) -> Vec<&'a Mapping> {
+    let mut segments: Vec<&Mapping> = mappings.iter().filter(|m| m.path == exe).collect();
+    if let Some(end) = segments.iter().map(|m| m.end_addr).max()
+        && let Some(bss) = mappings
+            .iter()
+            .find(|m| m.start_addr == end && m.path.is_empty() && m.inode == 0)
+    {
+        segments.push(bss);
+    }
+    segments
+}
+
+// Anything bigger than this is more likely a buffer or an arena than a TLS block
+const TLS_MAX_SIZE: u64 = 1 << 20;
+
+// How much of the top of a thread's stack mapping to count as its TLS; enough for glibc's thread
+// descriptor and a typical static TLS block
+const TLS_TOP_SIZE: u64 = 4096;
+
+// The regions that probably hold thread-local storage. Nothing in maps says which they are, so this
+// guesses in two ways:
+//
+// - glibc carves each new thread's descriptor and static TLS out of the top of the thread's stack
+//   mapping, so the last TLS_TOP_SIZE bytes of every thread stack but the main one count. These
+//   regions are slices of a mapping rather than whole ones.
+// - Small anonymous rw-p mappings right next to a thread stack, or on the far side of its guard
+//   page (a ---p mapping just below it), are taken to be dynamic TLS blocks.
+//
+// Like heap_regions this is only a heuristic. It misses the main thread's TLS, which ld.so puts
+// wherever its allocator happens to be, and other libcs lay threads out differently.
+pub fn tls_regions(pid: u32) -> Result<Vec<Mapping>> {
+    let mappings = get_memmap(pid)?;
+    let stacks = thread_stacks(pid)?;
+    let mut regions: Vec<Mapping> = stacks
+        .iter()
+        .filter(|s| s.kind() != MappingKind::Stack && s.size() > TLS_TOP_SIZE)
+        .map(|s| {
+            let mut top = s.clone();
+            top.start_addr = s.end_addr - TLS_TOP_SIZE;
+            top.offset += top.start_addr - s.start_addr;
+            top
+        })
+        .collect();
+
+    let (mut low, high): (Vec<u64>, Vec<u64>) =
+        stacks.iter().map(|s| (s.start_addr, s.end_addr)).unzip();
+    // Let a stack's border reach past its guard page, so the mapping below that counts as next to it
+    low.extend(guard_pages(&mappings, &stacks).iter().map(|g| g.start_addr));
+    regions.extend(mappings.into_iter().filter(|m| {
+        m.path.is_empty()
+            && m.inode == 0
+            && m.permissions == PermissionSet::from("rw-p")
+            && m.size() <= TLS_MAX_SIZE
+            && !stacks.iter().any(|s| s.start_addr == m.start_addr)
+            && (low.contains(&m.end_addr) || high.contains(&m.start_addr))
+    }));
+    regions.sort_by_key(|m| m.start_addr);
+    Ok(regions)
+}
+
+// Compare two snapshots of a memory map. Mappings are matched on their address range and path, so
+// a region that is remapped elsewhere shows up as one removal and one addition.
+pub fn diff_maps(old: &[Mapping], new: &[Mapping]) -> Vec<MapChange> {
+    let key = |m: &Mapping| (m.start_addr, m.end_addr, m.path.clone());
+    let old_by_key: HashMap<_, &Mapping> = old.iter().map(|m| (key(m), m)).collect();
+    let new_by_key: HashMap<_, &Mapping> = new.iter().map(|m| (key(m), m)).collect();
+
+    let mut changes = Vec::new();
+    for m in old {
+        if !new_by_key.contains_key(&key(m)) {
+            changes.push(MapChange::Removed(m.clone()));
+        }
+    }
+    for m in new {
+        match old_by_key.get(&key(m)) {
+            None => changes.push(MapChange::Added(m.clone())),
+            Some(prev) if prev.permissions != m.permissions => changes.push(MapChange::Changed {
+                old: (*prev).clone(),
+                new: m.clone(),
+            }),
+            Some(_) => {}
+        }
+    }
+
+    changes
+}
+
+/// A hash of the parts of a layout that ASLR doesn't move: the set of (basename, permissions,
+/// size) of its mappings. Two runs of the same program in the same environment get the same
+/// fingerprint, which makes it good for grouping crash reports. The hash is FNV-1a, so unlike
+/// std's randomly keyed hasher it's the same from one run (and build) to the next.
+///
+/// ```
+/// use rust_roulette::memmap::{Mapping, layout_fingerprint};
+///
+/// let layout = |base: u64| {
+///     vec![
+///         Mapping::new(base, base + 0x1000, "r--p".into(), 0, "fd:00".into(), 7, "/bin/app".into()),
+///         Mapping::new(base + 0x1000, base + 0x3000, "r-xp".into(), 0x1000, "fd:00".into(), 7, "/bin/app".into()),
+///     ]
+/// };
+/// assert_eq!(layout_fingerprint(&layout(0x55550000)), layout_fingerprint(&layout(0x7f000000)));
+/// ```
+pub fn layout_fingerprint(mappings: &[Mapping]) -> u64 {
+    let mut shapes: Vec<(&str, String, u64)> = mappings
+        .iter()
+        .map(|m| (m.basename(), m.permissions.to_string(), m.size()))
+        .collect();
+    shapes.sort();
+    shapes.dedup();
+
+    const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+    const FNV_PRIME: u64 = 0x100000001b3;
+    let mut hash = FNV_OFFSET;
+    let mut feed = |bytes: &[u8]| {
+        for &byte in bytes {
+            hash = (hash ^ byte as u64).wrapping_mul(FNV_PRIME);
+        }
+    };
+    for (basename, perms, size) in &shapes {
+        // The NUL keeps "ab" + "c" from hashing like "a" + "bc"
+        feed(basename.as_bytes());
+        feed(&[0]);
+        feed(perms.as_bytes());
+        feed(&size.to_le_bytes());
+    }
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_reversed_range_is_a_line_error() {
+        let line = "7f2c4000-7f2c3000 rw-p 00000000 00:00 0";
+        assert!(matches!(
+            Mapping::try_from(line),
+            Err(LineError::ReversedAddress(field)) if field == "7f2c4000-7f2c3000"
+        ));
+        // And the parser skips it, counting it among the errors
+        let text = format!("{}\n7f2c3000-7f2c4000 rw-p 00000000 00:00 0\n", line);
+        let parsed = parse_memmap(text.as_bytes()).unwrap();
+        assert_eq!(parsed.mappings.len(), 1);
+        assert_eq!(parsed.errors.len(), 1);
+        assert!(parsed.errors[0].starts_with("line 1: "));
+    }
+
+    #[test]
+    fn size_of_a_reversed_range_is_zero() {
+        let mapping = Mapping::new(
+            0x2000,
+            0x1000,
+            "rw-p".into(),
+            0,
+            "00:00".into(),
+            0,
+            "".into(),
+        );
+        assert_eq!(mapping.size(), 0);
+    }
+
+    #[test]
+    fn permissions_survive_a_trip_through_prot_flags() {
+        let (r, w, x) = (
+            ProtFlags::PROT_READ,
+            ProtFlags::PROT_WRITE,
+            ProtFlags::PROT_EXEC,
+        );
+        for (perms, expected) in [
+            ("---p", ProtFlags::empty()),
+            ("r--p", r),
+            ("-w-p", w),
+            ("--xp", x),
+            ("rw-p", r | w),
+            ("r-xp", r | x),
+            ("-wxp", w | x),
+            ("rwxp", r | w | x),
+        ] {
+            let set = PermissionSet::from(perms);
+            let flags: ProtFlags = set.clone().into();
+            // Exactly these bits, so nothing like PROT_GROWSDOWN sneaks in
+            assert_eq!(flags, expected, "{}", perms);
+            assert_eq!(PermissionSet::from_prot_flags(flags), set, "{}", perms);
         }
-        let addresses = fields[0]
-            .split("-")
-            .map(|s| u64::from_str_radix(s, 16))
-            .collect::<Result<Vec<u64>, _>>()
-            .context(format!("Failed to parse addresses '{}'", fields[0]))?;
-        if addresses.len() != 2 {
-            println!(
-                "Failed to parse addressess: {}, got {:?}",
-                fields[0], addresses
+        // ProtFlags can't say a mapping is shared, so that's lost on the way
+        let shared = PermissionSet::from("rw-s");
+        let back = PermissionSet::from_prot_flags(shared.clone().into());
+        assert!(!back.is_shared());
+        assert_eq!(back, shared.with_shared(false));
+    }
+
+    #[test]
+    fn mapping_predicates_follow_the_permissions() {
+        for (line, r, w, x, shared) in [
+            (
+                "55d0a0e02000-55d0a0e07000 r-xp 00002000 fd:01 1311 /usr/bin/cat",
+                true,
+                false,
+                true,
+                false,
+            ),
+            (
+                "55d0a0e07000-55d0a0e08000 r--p 00007000 fd:01 1311 /usr/bin/cat",
+                true,
+                false,
+                false,
+                false,
+            ),
+            (
+                "7f3c8a400000-7f3c8a421000 rw-p 00000000 00:00 0",
+                true,
+                true,
+                false,
+                false,
+            ),
+            (
+                "7f3c8a600000-7f3c8a601000 rw-s 00000000 00:05 1234 /dev/shm/ring",
+                true,
+                true,
+                false,
+                true,
+            ),
+            (
+                "7f3c8a700000-7f3c8a710000 rwxp 00000000 00:00 0",
+                true,
+                true,
+                true,
+                false,
+            ),
+            (
+                "7f3c8a800000-7f3c8a801000 ---p 00000000 00:00 0",
+                false,
+                false,
+                false,
+                false,
+            ),
+            (
+                "7f3c8a900000-7f3c8a901000 --xs 00000000 fd:01 9 /tmp/odd",
+                false,
+                false,
+                true,
+                true,
+            ),
+        ] {
+            let mapping = Mapping::try_from(line).ok().unwrap();
+            let perms = &mapping.permissions;
+            assert_eq!(
+                (
+                    mapping.is_readable(),
+                    mapping.is_writable(),
+                    mapping.is_executable()
+                ),
+                (r, w, x),
+                "{}",
+                line
             );
-            continue;
+            assert_eq!(
+                (
+                    perms.is_readable(),
+                    perms.is_writable(),
+                    perms.is_executable(),
+                    perms.is_shared()
+                ),
+                (r, w, x, shared),
+                "{}",
+                line
+            );
+            // The older names say the same
+            assert_eq!(
+                (
+                    perms.readable(),
+                    perms.writeable(),
+                    perms.executable(),
+                    perms.shared()
+                ),
+                (r, w, x, shared),
+                "{}",
+                line
+            );
+            assert_eq!(mapping.is_wx(), w && x, "{}", line);
         }
-        let perms = fields[1];
-        let path = {
-            if fields.len() > 5 {
-                fields[5..].join(" ")
-            } else {
-                "".to_string()
+    }
+
+    #[test]
+    fn permissions_format_and_parse_back_the_same() {
+        for r in ["r", "-"] {
+            for w in ["w", "-"] {
+                for x in ["x", "-"] {
+                    for p in ["p", "s"] {
+                        let text = format!("{}{}{}{}", r, w, x, p);
+                        let perms: PermissionSet = text.parse().unwrap();
+                        assert_eq!(perms.to_string(), text);
+                    }
+                }
             }
+        }
+        assert!("rwx".parse::<PermissionSet>().is_err());
+        assert!("xwrp".parse::<PermissionSet>().is_err());
+    }
+
+    #[test]
+    fn file_offsets_resolve_through_the_segment_that_maps_them() {
+        // A library's text mapped from 0x3000 into the file, and its data from 0x9000
+        let segment = |start: u64, end: u64, perms: &str, offset| {
+            Mapping::new(
+                start,
+                end,
+                perms.into(),
+                offset,
+                "fd:00".into(),
+                9,
+                "/lib/libbaz.so.2".into(),
+            )
         };
-        results.push(Mapping::new(
-            addresses[0],
-            addresses[1],
-            perms.to_string(),
-            path.to_string(),
-        ));
+        let mappings = [
+            segment(0x7f10_0000, 0x7f10_3000, "r-xp", 0x3000),
+            segment(0x7f10_8000, 0x7f10_9000, "rw-p", 0x9000),
+        ];
+        // start_addr - offset + file_offset, in whichever segment covers the offset
+        assert_eq!(
+            resolve_file_offset(&mappings, "libbaz.so.2", 0x3000),
+            Some(0x7f10_0000)
+        );
+        assert_eq!(
+            resolve_file_offset(&mappings, "libbaz.so.2", 0x4abc),
+            Some(0x7f10_1abc)
+        );
+        assert_eq!(
+            resolve_file_offset(&mappings, "libbaz.so.2", 0x9010),
+            Some(0x7f10_8010)
+        );
+        // Before the first segment, between the two, and past the last
+        assert_eq!(resolve_file_offset(&mappings, "libbaz.so.2", 0x2fff), None);
+        assert_eq!(resolve_file_offset(&mappings, "libbaz.so.2", 0x6000), None);
+        assert_eq!(resolve_file_offset(&mappings, "libbaz.so.2", 0xa000), None);
+        // Only the basename is matched
+        assert_eq!(
+            resolve_file_offset(&mappings, "/lib/libbaz.so.2", 0x3000),
+            None
+        );
+    }
+
+    #[test]
+    fn addresses_and_file_offsets_translate_both_ways() {
+        // Four pages of a library's text, mapped from 0x1c000 into the file
+        let text = Mapping::new(
+            0x7f00_4000,
+            0x7f00_8000,
+            "r-xp".into(),
+            0x1c000,
+            "fd:00".into(),
+            3,
+            "/lib/libqux.so".into(),
+        );
+        assert_eq!(text.vaddr_to_file_offset(0x7f00_4000), Some(0x1c000));
+        assert_eq!(text.vaddr_to_file_offset(0x7f00_7fff), Some(0x1ffff));
+        assert_eq!(text.vaddr_to_file_offset(0x7f00_8000), None);
+        assert_eq!(text.vaddr_to_file_offset(0x7f00_3fff), None);
+
+        assert_eq!(text.file_offset_to_vaddr(0x1c000), Some(0x7f00_4000));
+        assert_eq!(text.file_offset_to_vaddr(0x1d234), Some(0x7f00_5234));
+        assert_eq!(text.file_offset_to_vaddr(0x20000), None);
+        // Below the segment's offset isn't in it either, rather than wrapping around
+        assert_eq!(text.file_offset_to_vaddr(0x1bfff), None);
+
+        for vaddr in [0x7f00_4000, 0x7f00_5678, 0x7f00_7fff] {
+            let offset = text.vaddr_to_file_offset(vaddr).unwrap();
+            assert_eq!(text.file_offset_to_vaddr(offset), Some(vaddr));
+        }
     }
 
-    Ok(results)
+    #[test]
+    fn named_anonymous_mappings_are_anonymous() {
+        let line = "7f3a2c000000-7f3a2c021000 rw-p 00000000 00:00 0                          [anon:libc_malloc]";
+        let mapping = Mapping::try_from(line).unwrap();
+        assert_eq!(mapping.path, "[anon:libc_malloc]");
+        assert_eq!(mapping.anon_name(), Some("libc_malloc"));
+        assert_eq!(mapping.kind(), MappingKind::Anonymous);
+
+        // Other bracketed names aren't anonymous, even ones that almost look it
+        for (path, kind) in [
+            ("[heap]", MappingKind::Heap),
+            ("[anon:", MappingKind::Special),
+            ("[anonymous]", MappingKind::Special),
+        ] {
+            let line = format!("7f3a2c000000-7f3a2c021000 rw-p 00000000 00:00 0 {}", path);
+            let mapping = Mapping::try_from(line.as_str()).unwrap();
+            assert_eq!(mapping.anon_name(), None, "{}", path);
+            assert_eq!(mapping.kind(), kind, "{}", path);
+        }
+    }
+
+    #[test]
+    fn local_addresses_convert_without_truncating() {
+        assert_eq!(local_addr(0).unwrap(), 0);
+        assert_eq!(local_addr(0xffff_f000).unwrap(), 0xffff_f000);
+        assert_eq!(local_addr(usize::MAX as u64).unwrap(), usize::MAX);
+    }
+
+    #[cfg(target_pointer_width = "32")]
+    #[test]
+    fn a_64_bit_address_is_rejected_on_a_32_bit_build() {
+        for addr in [0x1_0000_0000, 0x7ffd_1be0_0000, u64::MAX] {
+            let error = local_addr(addr).unwrap_err().to_string();
+            assert!(
+                error.contains("doesn't fit in a 32-bit pointer"),
+                "{}",
+                error
+            );
+        }
+    }
+
+    #[test]
+    fn fields_split_on_any_whitespace_but_the_path_is_kept_whole() {
+        let parse = |line: &str| Mapping::try_from(line).ok().unwrap();
+        // Tabs between the fields, as some tools write when they re-save a map
+        let tabbed = parse("7f00a000-7f00c000\tr-xp\t00001000\tfd:01\t77\t/opt/app/lib.so");
+        assert_eq!(
+            (
+                tabbed.start_addr,
+                tabbed.end_addr,
+                tabbed.offset,
+                tabbed.inode
+            ),
+            (0x7f00a000, 0x7f00c000, 0x1000, 77)
+        );
+        assert_eq!(tabbed.permissions.to_string(), "r-xp");
+        assert_eq!(tabbed.device, "fd:01");
+        assert_eq!(tabbed.path, "/opt/app/lib.so");
+
+        for path in [
+            "/home/me/my  two  spaces.so",
+            "/tmp/tab\tinside",
+            "/tmp/trailing space ",
+            "/usr/lib/libgone.so (deleted)",
+        ] {
+            let line = format!("7f00a000-7f00c000 r--p 00000000 fd:01 77 \t   {}", path);
+            assert_eq!(parse(&line).path, path);
+        }
+        assert_eq!(parse("7f00a000-7f00c000 rw-p 00000000 00:00 0").path, "");
+        assert_eq!(
+            parse("7f00a000-7f00c000 rw-p 00000000 00:00 0 \t ").path,
+            ""
+        );
+    }
+
+    #[test]
+    fn lines_that_overlap_or_go_backwards_are_dropped_and_counted() {
+        let text = "\
+00400000-00402000 r--p 00000000 fd:00 1 /a
+00401000-00403000 r--p 00000000 fd:00 1 /b
+00402000-00403000 r--p 00000000 fd:00 1 /c
+00100000-00200000 r--p 00000000 fd:00 1 /d
+not a mapping
+00403000-00404000 r--p 00000000 fd:00 1 /e
+";
+        let parsed = parse_memmap(text.as_bytes()).unwrap();
+        let paths: Vec<&str> = parsed.mappings.iter().map(|m| m.path.as_str()).collect();
+        assert_eq!(paths, ["/a", "/c", "/e"]);
+        assert_eq!(parsed.overlaps, 2);
+        assert_eq!(parsed.errors.len(), 3);
+        assert_eq!(
+            parsed.errors[0],
+            "line 2: 0x401000-0x403000 starts before the mapping above it ends, at 0x402000"
+        );
+        assert!(parsed.errors[1].starts_with("line 4: "));
+        assert!(parsed.errors[2].starts_with("line 5: "));
+    }
+
+    // A few lines of a real map, with an anonymous mapping and a path with a space in it
+    const FIXTURE: &str = "\
+55d0a0e00000-55d0a0e02000 r--p 00000000 fd:01 1311   /usr/bin/cat
+55d0a0e02000-55d0a0e07000 r-xp 00002000 fd:01 1311   /usr/bin/cat
+7f3c8a400000-7f3c8a421000 rw-p 00000000 00:00 0
+7f3c8a600000-7f3c8a628000 r--p 00000000 fd:01 2048   /usr/lib/my lib.so
+7ffd1be00000-7ffd1be21000 rw-p 00000000 00:00 0      [stack]
+";
+
+    // A file of our own under the temp dir, removed again when dropped
+    struct TempFile(std::path::PathBuf);
+
+    impl TempFile {
+        fn new(name: &str, contents: &[u8]) -> TempFile {
+            let path = std::env::temp_dir().join(format!(
+                "rust_roulette-test-{}-{}",
+                std::process::id(),
+                name
+            ));
+            fs::write(&path, contents).unwrap();
+            TempFile(path)
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    fn fields(mappings: &[Mapping]) -> Vec<(u64, u64, String, u64, String, u64, String)> {
+        mappings
+            .iter()
+            .map(|m| {
+                let perms = m.permissions.to_string();
+                (
+                    m.start_addr,
+                    m.end_addr,
+                    perms,
+                    m.offset,
+                    m.device.clone(),
+                    m.inode,
+                    m.path.clone(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn a_gzipped_maps_file_loads_like_the_plain_one() {
+        use flate2::Compression;
+        use flate2::write::GzEncoder;
+        use std::io::Write;
+
+        let mut gz = GzEncoder::new(Vec::new(), Compression::default());
+        gz.write_all(FIXTURE.as_bytes()).unwrap();
+        let plain = TempFile::new("maps", FIXTURE.as_bytes());
+        let gzipped = TempFile::new("maps.gz", &gz.finish().unwrap());
+
+        let expected = load_memmap(&plain.0).unwrap();
+        assert_eq!(expected.len(), 5);
+        assert_eq!(fields(&load_memmap(&gzipped.0).unwrap()), fields(&expected));
+    }
+
+    #[test]
+    fn a_corrupt_gzip_file_is_an_error() {
+        let bad = TempFile::new("bad.gz", b"\x1f\x8b not really gzip");
+        assert!(load_memmap(&bad.0).is_err());
+    }
+
+    // What a /proc/PID directory looks like once archived: the task's own maps comes before the
+    // process's, which has to win anyway
+    const ARCHIVED: [(&str, &str); 3] = [
+        (
+            "1234/task/1234/maps",
+            "7f0000000000-7f0000001000 r--p 00000000 00:00 0\n",
+        ),
+        ("1234/maps", FIXTURE),
+        ("1234/smaps", "not a maps file\n"),
+    ];
+
+    fn tarball() -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (name, contents) in ARCHIVED {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(contents.len() as u64);
+            header.set_mode(0o444);
+            header.set_cksum();
+            builder
+                .append_data(&mut header, name, contents.as_bytes())
+                .unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    #[test]
+    fn maps_files_load_out_of_archives() {
+        use flate2::Compression;
+        use flate2::write::GzEncoder;
+        use std::io::Write;
+
+        let expected = fields(&parse_memmap(FIXTURE.as_bytes()).unwrap().mappings);
+        assert_eq!(expected.len(), 5);
+
+        let tar = TempFile::new("proc.tar", &tarball());
+        assert_eq!(fields(&load_memmap(&tar.0).unwrap()), expected);
+
+        let mut gz = GzEncoder::new(Vec::new(), Compression::default());
+        gz.write_all(&tarball()).unwrap();
+        let gz = gz.finish().unwrap();
+        for name in ["proc.tar.gz", "proc.tgz"] {
+            let tgz = TempFile::new(name, &gz);
+            assert_eq!(fields(&load_memmap(&tgz.0).unwrap()), expected, "{}", name);
+        }
+
+        let mut zip = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        for (name, contents) in ARCHIVED {
+            zip.start_file(name, zip::write::SimpleFileOptions::default())
+                .unwrap();
+            zip.write_all(contents.as_bytes()).unwrap();
+        }
+        let zip = TempFile::new("proc.zip", &zip.finish().unwrap().into_inner());
+        assert_eq!(fields(&load_memmap(&zip.0).unwrap()), expected);
+    }
+
+    #[test]
+    fn an_archive_without_a_maps_file_is_an_error() {
+        let mut builder = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        header.set_size(4);
+        header.set_cksum();
+        builder
+            .append_data(&mut header, "1234/smaps", &b"nope"[..])
+            .unwrap();
+        let tar = TempFile::new("nomaps.tar", &builder.into_inner().unwrap());
+        let error = load_memmap(&tar.0).err().unwrap().to_string();
+        assert!(error.starts_with("No maps file in "), "{}", error);
+
+        let not_zip = TempFile::new("bad.zip", b"PK not really a zip");
+        assert!(load_memmap(&not_zip.0).is_err());
+    }
+
+    #[test]
+    fn diffs_show_what_was_added_removed_and_reprotected() {
+        let parse = |text: &str| parse_memmap(text.as_bytes()).unwrap().mappings;
+        let old = parse(FIXTURE);
+        // The library is unloaded, the anonymous mapping made read-only, and a new one mapped in
+        let new = parse(
+            "\
+55d0a0e00000-55d0a0e02000 r--p 00000000 fd:01 1311   /usr/bin/cat
+55d0a0e02000-55d0a0e07000 r-xp 00002000 fd:01 1311   /usr/bin/cat
+7f3c8a400000-7f3c8a421000 r--p 00000000 00:00 0
+7f3c8a700000-7f3c8a710000 rw-p 00000000 00:00 0
+7ffd1be00000-7ffd1be21000 rw-p 00000000 00:00 0      [stack]
+",
+        );
+        let changes: Vec<String> = diff_maps(&old, &new)
+            .iter()
+            .map(|c| c.to_string())
+            .collect();
+        assert_eq!(
+            changes,
+            [
+                "- Mapping(0x7f3c8a600000-0x7f3c8a628000, perms=r--p, path=/usr/lib/my lib.so)",
+                "~ Mapping(0x7f3c8a400000-0x7f3c8a421000, perms=r--p, path=) (rw-p -> r--p)",
+                "+ Mapping(0x7f3c8a700000-0x7f3c8a710000, perms=rw-p, path=)",
+            ]
+        );
+
+        assert!(diff_maps(&old, &old).is_empty());
+        // The same range under another name is a different mapping, not a change of this one
+        let renamed = parse("7ffd1be00000-7ffd1be21000 rw-p 00000000 00:00 0 [heap]\n");
+        let stack = parse("7ffd1be00000-7ffd1be21000 rw-p 00000000 00:00 0 [stack]\n");
+        let changes: Vec<String> = diff_maps(&stack, &renamed)
+            .iter()
+            .map(|c| c.to_string())
+            .collect();
+        assert_eq!(
+            changes,
+            [
+                "- Mapping(0x7ffd1be00000-0x7ffd1be21000, perms=rw-p, path=[stack])",
+                "+ Mapping(0x7ffd1be00000-0x7ffd1be21000, perms=rw-p, path=[heap])",
+            ]
+        );
+    }
+
+    #[test]
+    fn only_inaccessible_anonymous_pages_right_below_a_stack_are_guards() {
+        let parse = |text: &str| parse_memmap(text.as_bytes()).unwrap().mappings;
+        let mappings = parse(
+            "\
+7f1000000000-7f1000001000 ---p 00000000 00:00 0
+7f1000001000-7f1000801000 rw-p 00000000 00:00 0
+7f2000000000-7f2000001000 ---p 00000000 00:00 0
+7f2000002000-7f2000802000 rw-p 00000000 00:00 0
+7f3000000000-7f3000001000 r--p 00000000 00:00 0
+7f3000001000-7f3000801000 rw-p 00000000 00:00 0
+7f4000000000-7f4000001000 ---p 00001000 fd:01 99   /usr/lib/libfoo.so
+7f4000001000-7f4000801000 rw-p 00000000 00:00 0
+7ffd00000000-7ffd00021000 rw-p 00000000 00:00 0      [stack]
+",
+        );
+        // Pretend every rw-p mapping is a thread's stack
+        let stacks: Vec<Mapping> = mappings
+            .iter()
+            .filter(|m| m.permissions == PermissionSet::from("rw-p"))
+            .cloned()
+            .collect();
+        let guards = guard_pages(&mappings, &stacks);
+        // The first is a real guard. The others are a page short of their stack, readable, or
+        // part of a file, and [stack] has nothing below it at all.
+        assert_eq!(guards.len(), 1);
+        assert_eq!(
+            (guards[0].start_addr, guards[0].end_addr),
+            (0x7f1000000000, 0x7f1000001000)
+        );
+        assert!(guard_pages(&mappings, &[]).is_empty());
+    }
 }
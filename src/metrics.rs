@@ -0,0 +1,53 @@
+use anyhow::{Context, Result};
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+// The latest metrics, already in Prometheus' text format. The game renders them after every round
+// and the server just hands out whatever is there, so a scrape never waits on a shot.
+static LATEST: Mutex<String> = Mutex::new(String::new());
+
+pub fn publish(text: String) {
+    *LATEST.lock().unwrap() = text;
+}
+
+// Serve the metrics on every path of a bare-bones HTTP server, from a thread of its own
+pub fn serve(port: u16) -> Result<()> {
+    let listener = TcpListener::bind(("0.0.0.0", port))
+        .context(format!("Failed to listen for metrics on port {}", port))?;
+    eprintln!("Serving metrics on port {}", port);
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            // A scraper that hangs up or misbehaves only loses its own response
+            let _ = respond(stream);
+        }
+    });
+    Ok(())
+}
+
+fn respond(mut stream: TcpStream) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(1)))?;
+    // Whatever was asked for, the answer is the same, but the headers have to be read first
+    let mut reader = BufReader::new(&stream);
+    let mut line = String::new();
+    while reader.read_line(&mut line)? > 0 && line != "\r\n" && line != "\n" {
+        line.clear();
+    }
+    let body = LATEST.lock().unwrap().clone();
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        body.len(),
+        body
+    )
+}
+
+// Prometheus label values are quoted, so quotes, backslashes and newlines need escaping
+pub fn label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
@@ -0,0 +1,78 @@
+use crate::allowlist::glob_match;
+use crate::json::{self, Value};
+use crate::memmap::{Mapping, PermissionSet};
+use anyhow::{Context, Result, anyhow};
+use std::fs;
+use std::path::Path;
+
+/// A `--perm-config` file: a JSON object from mapping patterns to the permissions to give them
+/// before the game. Patterns are basenames or globs, matched against the whole path if they have a
+/// `/`, and an exact name like `[heap]` matches too, brackets and all. The first pattern that
+/// matches wins, so more specific ones go first.
+///
+/// ```
+/// use rust_roulette::memmap::{Mapping, PermissionSet};
+/// use rust_roulette::permconfig::PermConfig;
+///
+/// let config = PermConfig::parse(r#"{"libc.so.*": "r-xp", "[heap]": "rwxp", "*": "rw-p"}"#)?;
+/// let mapping = |path: &str| Mapping::new(0x1000, 0x2000, "r--p".into(), 0, "00:00".into(), 0, path.into());
+/// let perms = |path| config.lookup(&mapping(path)).map(ToString::to_string);
+/// assert_eq!(perms("/usr/lib/libc.so.6").as_deref(), Some("r-xp"));
+/// assert_eq!(perms("[heap]").as_deref(), Some("rwxp"));
+/// assert_eq!(perms("/usr/lib/libm.so.6").as_deref(), Some("rw-p"));
+/// // Nothing to match an anonymous mapping by
+/// assert_eq!(perms(""), None);
+///
+/// assert!(PermConfig::parse(r#"{"libc.so.*": "rwx"}"#).is_err());
+/// assert!(PermConfig::parse(r#"["libc.so.*"]"#).is_err());
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+pub struct PermConfig {
+    rules: Vec<(String, PermissionSet)>,
+}
+
+impl PermConfig {
+    pub fn load(path: &Path) -> Result<PermConfig> {
+        let text =
+            fs::read_to_string(path).context(format!("Failed to read {}", path.display()))?;
+        PermConfig::parse(&text).context(format!("Invalid permission config {}", path.display()))
+    }
+
+    pub fn parse(text: &str) -> Result<PermConfig> {
+        let Value::Object(fields) = json::parse(text)? else {
+            return Err(anyhow!(
+                "expected an object of patterns and permissions, like {{\"libc.so.*\": \"r-xp\"}}"
+            ));
+        };
+        let rules = fields
+            .into_iter()
+            .map(|(pattern, perms)| {
+                let perms = perms
+                    .as_str()
+                    .ok_or(anyhow!("the permissions for '{}' aren't a string", pattern))?
+                    .parse()
+                    .context(format!("Invalid permissions for '{}'", pattern))?;
+                Ok((pattern, perms))
+            })
+            .collect::<Result<_>>()?;
+        Ok(PermConfig { rules })
+    }
+
+    // The permissions the first matching pattern asks for, if any does
+    pub fn lookup(&self, mapping: &Mapping) -> Option<&PermissionSet> {
+        if mapping.path.is_empty() {
+            return None;
+        }
+        self.rules
+            .iter()
+            .find(|(pattern, _)| {
+                let name = if pattern.contains('/') {
+                    mapping.path.as_str()
+                } else {
+                    mapping.basename()
+                };
+                pattern == name || glob_match(pattern, name)
+            })
+            .map(|(_, perms)| perms)
+    }
+}
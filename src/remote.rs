@@ -0,0 +1,223 @@
+use crate::format::{OutputFormat, Units, format_mappings, json_string, parse_mappings_json};
+use crate::json;
+use crate::memmap::{Mapping, get_memmap};
+use anyhow::{Context, Result, anyhow};
+use std::fs;
+use std::io::{ErrorKind, Read, Write};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::UnixListener;
+use std::path::Path;
+use std::thread;
+
+// The protocol between --serve and --remote: each message is a 4-byte big-endian length followed by
+// that much JSON. A request is `{"pid": N}`, and the answer is the same array of mappings that
+// `--list --format json` prints, or `{"error": "..."}`. A connection can carry any number of
+// requests, one answer each, in order.
+
+// Far more than any real map, but small enough that a garbled length can't make us allocate wildly
+const MAX_FRAME: usize = 64 << 20;
+
+fn write_frame(out: &mut impl Write, payload: &str) -> Result<()> {
+    let len = u32::try_from(payload.len())
+        .ok()
+        .filter(|&len| len as usize <= MAX_FRAME)
+        .ok_or(anyhow!(
+            "{} bytes is too big for one message",
+            payload.len()
+        ))?;
+    out.write_all(&len.to_be_bytes())?;
+    out.write_all(payload.as_bytes())?;
+    out.flush()?;
+    Ok(())
+}
+
+// The next message, or None if the other side hung up cleanly between messages
+fn read_frame(input: &mut impl Read) -> Result<Option<String>> {
+    let mut len = [0u8; 4];
+    match input.read_exact(&mut len) {
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        result => result.context("Failed to read a message length")?,
+    }
+    let len = u32::from_be_bytes(len) as usize;
+    if len > MAX_FRAME {
+        return Err(anyhow!("a {} byte message is too big", len));
+    }
+    let mut payload = vec![0u8; len];
+    input
+        .read_exact(&mut payload)
+        .context("Failed to read a message")?;
+    String::from_utf8(payload)
+        .map(Some)
+        .map_err(|_| anyhow!("a message isn't UTF-8"))
+}
+
+/// Answer requests on one connection until the client hangs up. A request that can't be answered
+/// gets an error back rather than ending the connection; only a broken connection does that.
+///
+/// ```
+/// use rust_roulette::remote::{request_memmap, serve_connection};
+/// use std::os::unix::net::UnixStream;
+///
+/// let (server, mut client) = UnixStream::pair()?;
+/// std::thread::spawn(move || serve_connection(server));
+/// let mappings = request_memmap(&mut client, std::process::id())?;
+/// assert!(mappings.iter().any(|m| m.path == "[stack]"));
+/// // No such process, but the connection carries on
+/// assert!(request_memmap(&mut client, u32::MAX).is_err());
+/// assert!(!request_memmap(&mut client, std::process::id())?.is_empty());
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+pub fn serve_connection<S: Read + Write>(mut stream: S) -> Result<()> {
+    while let Some(request) = read_frame(&mut stream)? {
+        let answer = match answer(&request) {
+            Ok(mappings) => format_mappings(&mappings, OutputFormat::Json, Units::Bytes),
+            Err(e) => format!("{{\"error\": {}}}", json_string(&format!("{:#}", e))),
+        };
+        write_frame(&mut stream, &answer)?;
+    }
+    Ok(())
+}
+
+fn answer(request: &str) -> Result<Vec<Mapping>> {
+    let pid = json::parse(request)?
+        .get("pid")
+        .and_then(json::Value::as_u64)
+        .and_then(|pid| u32::try_from(pid).ok())
+        .ok_or(anyhow!("expected a request like {{\"pid\": 1234}}"))?;
+    get_memmap(pid).context(format!("Failed to read the map of process {}", pid))
+}
+
+// Ask a server for the map of one of the processes it can see
+pub fn request_memmap<S: Read + Write>(stream: &mut S, pid: u32) -> Result<Vec<Mapping>> {
+    write_frame(stream, &format!("{{\"pid\": {}}}", pid))?;
+    let answer = read_frame(stream)?.ok_or(anyhow!("The server hung up without answering"))?;
+    if answer.trim_start().starts_with('{') {
+        let error = json::parse(&answer)?;
+        let message = error
+            .get("error")
+            .and_then(json::Value::as_str)
+            .unwrap_or("no reason given");
+        return Err(anyhow!(
+            "The server couldn't list process {}: {}",
+            pid,
+            message
+        ));
+    }
+    let parsed =
+        parse_mappings_json(&answer).context("The server's answer isn't a list of mappings")?;
+    for error in &parsed.errors {
+        eprintln!("{}", error);
+    }
+    Ok(parsed.mappings)
+}
+
+// Listen on a Unix socket at `path` for as long as we run, a thread per client. A socket left behind
+// by an earlier server is replaced, but anything else at the path is left alone.
+pub fn serve(path: &Path) -> Result<()> {
+    if fs::symlink_metadata(path).is_ok_and(|meta| meta.file_type().is_socket()) {
+        fs::remove_file(path).context(format!("Failed to remove old socket {}", path.display()))?;
+    }
+    let listener =
+        UnixListener::bind(path).context(format!("Failed to listen on {}", path.display()))?;
+    eprintln!("Serving memory maps on {}", path.display());
+    for stream in listener.incoming() {
+        let stream = stream.context("Failed to accept a connection")?;
+        thread::spawn(move || {
+            if let Err(e) = serve_connection(stream) {
+                eprintln!("Dropped a client: {:#}", e);
+            }
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::net::UnixStream;
+
+    // Paths that only survive if every layer quotes and escapes them properly
+    const AWKWARD: [&str; 5] = [
+        "/opt/my app/lib \"quoted\".so",
+        "/tmp/back\\slash, and comma",
+        "/tmp/new\nline\ttab",
+        "/usr/lib/libgone.so (deleted)",
+        "",
+    ];
+
+    fn mappings() -> Vec<Mapping> {
+        AWKWARD
+            .iter()
+            .enumerate()
+            .map(|(i, path)| {
+                let start = 0x7f00_0000_0000 + 0x10000 * i as u64;
+                Mapping::new(
+                    start,
+                    start + 0x3000,
+                    "r-xs".into(),
+                    0x2000 * i as u64,
+                    "fd:01".into(),
+                    100 + i as u64,
+                    path.to_string(),
+                )
+            })
+            .collect()
+    }
+
+    // Answer one request the way serve_connection would, but from a made-up map
+    fn serve_once(mut server: UnixStream, answer: String) {
+        thread::spawn(move || {
+            let request = read_frame(&mut server).unwrap().unwrap();
+            assert_eq!(request, "{\"pid\": 42}");
+            write_frame(&mut server, &answer).unwrap();
+        });
+    }
+
+    #[test]
+    fn mappings_with_awkward_paths_cross_the_wire_unchanged() {
+        let (server, mut client) = UnixStream::pair().unwrap();
+        let sent = mappings();
+        serve_once(
+            server,
+            format_mappings(&sent, OutputFormat::Json, Units::Bytes),
+        );
+        let received = request_memmap(&mut client, 42).unwrap();
+        assert_eq!(received.len(), sent.len());
+        for (sent, received) in sent.iter().zip(&received) {
+            assert_eq!(received.path, sent.path);
+            assert_eq!(
+                (received.start_addr, received.end_addr, received.offset),
+                (sent.start_addr, sent.end_addr, sent.offset)
+            );
+            assert_eq!(
+                (&received.device, received.inode),
+                (&sent.device, sent.inode)
+            );
+            assert_eq!(received.permissions, sent.permissions);
+        }
+    }
+
+    #[test]
+    fn a_server_error_comes_back_as_the_message() {
+        let (server, mut client) = UnixStream::pair().unwrap();
+        let reason = "can't read \"/proc/42/maps\": no\\such";
+        serve_once(server, format!("{{\"error\": {}}}", json_string(reason)));
+        let error = request_memmap(&mut client, 42).err().unwrap().to_string();
+        assert_eq!(
+            error,
+            format!("The server couldn't list process 42: {}", reason)
+        );
+    }
+
+    #[test]
+    fn oversized_and_torn_frames_are_errors() {
+        let mut huge = Vec::new();
+        huge.extend(((MAX_FRAME + 1) as u32).to_be_bytes());
+        assert!(read_frame(&mut &huge[..]).is_err());
+        // Cut off partway through the payload
+        let torn = [0, 0, 0, 10, b'[', b']'];
+        assert!(read_frame(&mut &torn[..]).is_err());
+        // Hanging up between frames is fine
+        assert!(read_frame(&mut &[][..]).unwrap().is_none());
+    }
+}
@@ -0,0 +1,159 @@
+use anyhow::{Context, Result, anyhow};
+use rand::rngs::StdRng;
+use rand::{RngCore, SeedableRng};
+use std::fs;
+use std::path::Path;
+
+// A seeded RNG that counts what it has handed out, so a run can be resumed from just the seed and
+// the count. StdRng's state can't be saved directly, so resuming re-seeds and throws away that many
+// draws. Every request is served from whole next_u64 calls, which makes one draw the only unit
+// there is and the replay exact whatever mix of calls the game made.
+pub struct SeededRng {
+    seed: u64,
+    draws: u64,
+    inner: StdRng,
+}
+
+impl SeededRng {
+    pub fn new(seed: u64) -> SeededRng {
+        SeededRng {
+            seed,
+            draws: 0,
+            inner: StdRng::seed_from_u64(seed),
+        }
+    }
+
+    // Pick up where a run that had made `draws` draws left off
+    pub fn resume(seed: u64, draws: u64) -> SeededRng {
+        let mut rng = SeededRng::new(seed);
+        for _ in 0..draws {
+            rng.next_u64();
+        }
+        rng
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn draws(&self) -> u64 {
+        self.draws
+    }
+}
+
+impl RngCore for SeededRng {
+    fn next_u32(&mut self) -> u32 {
+        self.next_u64() as u32
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.draws += 1;
+        self.inner.next_u64()
+    }
+
+    fn fill_bytes(&mut self, dst: &mut [u8]) {
+        for chunk in dst.chunks_mut(8) {
+            let bytes = self.next_u64().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+}
+
+// What --state-file records: enough to carry on with the same stream of shots
+pub struct GameState {
+    pub seed: u64,
+    pub draws: u64,
+    pub rounds: u64,
+}
+
+// Write the state to a temporary file first, so that being killed mid-write can't leave a torn
+// state file behind
+pub fn save_state(path: &Path, state: &GameState) -> Result<()> {
+    let tmp = path.with_extension("tmp");
+    fs::write(
+        &tmp,
+        format!(
+            "seed={}\ndraws={}\nrounds={}\n",
+            state.seed, state.draws, state.rounds
+        ),
+    )
+    .context(format!("Failed to write state to {}", tmp.display()))?;
+    fs::rename(&tmp, path).context(format!("Failed to replace {}", path.display()))
+}
+
+// Read a state file left by an earlier run, if there is one
+pub fn load_state(path: &Path) -> Result<Option<GameState>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let text =
+        fs::read_to_string(path).context(format!("Failed to read state {}", path.display()))?;
+    let field = |name: &str| -> Result<u64> {
+        let value = text
+            .lines()
+            .find_map(|line| line.strip_prefix(name)?.strip_prefix('='))
+            .ok_or(anyhow!("No {} in state file {}", name, path.display()))?;
+        value.parse().context(format!(
+            "Invalid {} '{}' in state file {}",
+            name,
+            value,
+            path.display()
+        ))
+    };
+    Ok(Some(GameState {
+        seed: field("seed")?,
+        draws: field("draws")?,
+        rounds: field("rounds")?,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::Rng;
+    use rand::seq::IndexedRandom;
+
+    // Draw the way the game does, with a mix of calls that each take a different number of words
+    fn draw(rng: &mut SeededRng) -> (u64, u32, bool, [u8; 12], u8) {
+        let mut bytes = [0; 12];
+        rng.fill_bytes(&mut bytes);
+        (
+            rng.random_range(0..1_000_000),
+            rng.next_u32(),
+            rng.random_bool(0.5),
+            bytes,
+            *[1, 2, 3, 4, 5].choose(rng).unwrap(),
+        )
+    }
+
+    #[test]
+    fn a_resumed_rng_carries_on_where_the_saved_one_was() {
+        let mut rng = SeededRng::new(0xdecaf);
+        for _ in 0..25 {
+            draw(&mut rng);
+        }
+        let path =
+            std::env::temp_dir().join(format!("rust_roulette-test-{}-state", std::process::id()));
+        save_state(
+            &path,
+            &GameState {
+                seed: rng.seed(),
+                draws: rng.draws(),
+                rounds: 25,
+            },
+        )
+        .unwrap();
+        let state = load_state(&path).unwrap().unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(
+            (state.seed, state.draws, state.rounds),
+            (0xdecaf, rng.draws(), 25)
+        );
+
+        let mut resumed = SeededRng::resume(state.seed, state.draws);
+        assert_eq!(resumed.draws(), rng.draws());
+        for _ in 0..10 {
+            assert_eq!(draw(&mut resumed), draw(&mut rng));
+        }
+    }
+}
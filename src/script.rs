@@ -0,0 +1,166 @@
+use anyhow::{Context, Result, anyhow};
+use rust_roulette::memmap::Mapping;
+
+// One line of a --script file: `basename offset length fill`, e.g. `libc.so.6 0x1c8000 64 0x00`.
+// The offset is relative to the lowest address the file is mapped at, so a script carries over
+// between runs whatever ASLR does. Blank lines and lines starting with # are ignored.
+pub struct ScriptLine {
+    pub line_no: usize,
+    pub basename: String,
+    pub offset: u64,
+    pub len: u64,
+    pub fill: u8,
+}
+
+// A script line pinned to a real address in the target
+pub struct ScriptedShot {
+    pub mapping_idx: usize,
+    pub addr: u64,
+    pub len: u64,
+    pub fill: u8,
+}
+
+pub fn parse_script(text: &str) -> Result<Vec<ScriptLine>> {
+    text.lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(line_no, line)| parse_line(line_no, line).context(format!("Line {}", line_no)))
+        .collect()
+}
+
+fn parse_line(line_no: usize, line: &str) -> Result<ScriptLine> {
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    let [basename, offset, len, fill] = fields[..] else {
+        return Err(anyhow!(
+            "expected 'basename offset length fill', got '{}'",
+            line
+        ));
+    };
+    let len: u64 = len.parse().context(format!("Invalid length '{}'", len))?;
+    if len == 0 {
+        return Err(anyhow!("length must be at least 1 byte"));
+    }
+    Ok(ScriptLine {
+        line_no,
+        basename: basename.to_string(),
+        offset: u64::from_str_radix(offset.strip_prefix("0x").unwrap_or(offset), 16)
+            .context(format!("Invalid hex offset '{}'", offset))?,
+        len,
+        fill: u8::from_str_radix(fill.strip_prefix("0x").unwrap_or(fill), 16)
+            .context(format!("Invalid hex fill byte '{}'", fill))?,
+    })
+}
+
+// Pin every line to an address using the target's full map, and check that each write lands
+// entirely inside one of the candidate mappings. Everything is checked before the first shot, so a
+// bad script never gets halfway.
+pub fn resolve_script(
+    lines: &[ScriptLine],
+    mappings: &[Mapping],
+    candidates: &[Mapping],
+) -> Result<Vec<ScriptedShot>> {
+    lines
+        .iter()
+        .map(|line| {
+            let base = mappings
+                .iter()
+                .filter(|m| m.basename() == line.basename)
+                .map(|m| m.start_addr)
+                .min()
+                .ok_or(anyhow!(
+                    "Line {}: '{}' isn't mapped",
+                    line.line_no,
+                    line.basename
+                ))?;
+            let addr = base.checked_add(line.offset).ok_or(anyhow!(
+                "Line {}: {}+0x{:x} is out of range",
+                line.line_no,
+                line.basename,
+                line.offset
+            ))?;
+            let mapping_idx = candidates
+                .iter()
+                .position(|m| {
+                    m.basename() == line.basename
+                        && m.contains_addr(addr)
+                        && line.len <= m.end_addr - addr
+                })
+                .ok_or(anyhow!(
+                    "Line {}: {} bytes at {}+0x{:x} don't fit in a candidate mapping",
+                    line.line_no,
+                    line.len,
+                    line.basename,
+                    line.offset
+                ))?;
+            Ok(ScriptedShot {
+                mapping_idx,
+                addr,
+                len: line.len,
+                fill: line.fill,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mapping(start: u64, end: u64, perms: &str, path: &str) -> Mapping {
+        Mapping::new(start, end, perms.into(), 0, "fd:01".into(), 42, path.into())
+    }
+
+    fn maps() -> Vec<Mapping> {
+        vec![
+            mapping(0x7f00_0000_0000, 0x7f00_0000_2000, "r--p", "/lib/libfoo.so"),
+            mapping(0x7f00_0000_2000, 0x7f00_0000_6000, "rw-p", "/lib/libfoo.so"),
+        ]
+    }
+
+    #[test]
+    fn a_good_line_resolves_from_the_lowest_mapping() {
+        let lines = parse_script("# a comment\n\n  libfoo.so 0x2010 16 0xAA\n").unwrap();
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0].line_no, 3);
+        let (mappings, candidates) = (maps(), maps()[1..].to_vec());
+        let shots = resolve_script(&lines, &mappings, &candidates).unwrap();
+        assert_eq!(shots[0].mapping_idx, 0);
+        assert_eq!(shots[0].addr, 0x7f00_0000_2010);
+        assert_eq!((shots[0].len, shots[0].fill), (16, 0xaa));
+    }
+
+    #[test]
+    fn bad_lines_are_errors() {
+        for text in [
+            "libfoo.so 0xnope 16 0xAA",
+            "libfoo.so 0x10000000000000000 16 0xAA",
+            "libfoo.so 0x10 0 0xAA",
+            "libfoo.so 0x10 16 0x100",
+            "libfoo.so 0x10 16",
+        ] {
+            assert!(parse_script(text).is_err(), "{}", text);
+        }
+
+        let resolve = |text: &str| {
+            let lines = parse_script(text).unwrap();
+            resolve_script(&lines, &maps(), &maps())
+                .err()
+                .unwrap()
+                .to_string()
+        };
+        // An offset that goes past the end of the address space rather than wrapping around
+        assert_eq!(
+            resolve("libfoo.so 0xffffffffffffffff 1 0x00"),
+            "Line 1: libfoo.so+0xffffffffffffffff is out of range"
+        );
+        assert_eq!(
+            resolve("libbar.so 0x10 1 0x00"),
+            "Line 1: 'libbar.so' isn't mapped"
+        );
+        assert_eq!(
+            resolve("libfoo.so 0x5ff8 16 0x00"),
+            "Line 1: 16 bytes at libfoo.so+0x5ff8 don't fit in a candidate mapping"
+        );
+    }
+}
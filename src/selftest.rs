@@ -0,0 +1,95 @@
+use anyhow::{Context, Result};
+use rust_roulette::format::{OutputFormat, Units, format_mappings};
+use rust_roulette::memmap::*;
+use std::env;
+use std::fs;
+use std::process;
+
+// Check the parser against our own live map, so that a user on an unusual kernel can tell whether
+// it copes before reporting a bug. Nothing here touches memory. Returns whether every check passed.
+pub fn selftest() -> Result<bool> {
+    let raw = fs::read_to_string(format!("/proc/{}/maps", process::id()))
+        .context("Failed to read our own memory map from procfs")?;
+    let parsed = parse_memmap(raw.as_bytes())?;
+    let mappings = &parsed.mappings;
+    let mut passed = true;
+    let mut check = |name: &str, problems: Vec<String>| {
+        if problems.is_empty() {
+            println!("PASS {}", name);
+        } else {
+            println!("FAIL {}", name);
+            for problem in &problems {
+                println!("       {}", problem);
+            }
+            passed = false;
+        }
+    };
+
+    check("every line parses", parsed.errors.clone());
+
+    check(
+        "every range is non-empty",
+        mappings
+            .iter()
+            .filter(|m| m.end_addr <= m.start_addr)
+            .map(|m| m.to_string())
+            .collect(),
+    );
+
+    // The parser drops any line that would break this, so check what the kernel printed
+    let printed: Vec<Mapping> = raw
+        .lines()
+        .filter_map(|line| Mapping::try_from(line).ok())
+        .collect();
+    check(
+        "mappings are sorted and don't overlap",
+        printed
+            .windows(2)
+            .filter(|pair| pair[1].start_addr < pair[0].end_addr)
+            .map(|pair| format!("{} then {}", pair[0], pair[1]))
+            .collect(),
+    );
+
+    // Compare against the raw field, which is what the kernel actually printed. Lines are matched
+    // up by start address, since any that failed to parse are missing from `mappings`.
+    check(
+        "permission strings round-trip",
+        raw.lines()
+            .filter_map(|line| {
+                let start = u64::from_str_radix(line.split('-').next()?, 16).ok()?;
+                let perms = line.split_whitespace().nth(1)?;
+                let m = mappings.iter().find(|m| m.start_addr == start)?;
+                (perms != m.permissions.to_string())
+                    .then(|| format!("'{}' came back as '{}'", perms, m.permissions))
+            })
+            .collect(),
+    );
+
+    let reformatted = format_mappings(mappings, OutputFormat::Maps, Units::Bytes);
+    let reparsed = parse_memmap(reformatted.as_bytes())?;
+    check(
+        "maps output re-parses to the same mappings",
+        if format_mappings(&reparsed.mappings, OutputFormat::Maps, Units::Bytes) == reformatted {
+            Vec::new()
+        } else {
+            vec![format!(
+                "{} mapping(s) in, {} out",
+                mappings.len(),
+                reparsed.mappings.len()
+            )]
+        },
+    );
+
+    let exe = env::current_exe().context("Failed to find our own executable")?;
+    let exe = exe.to_string_lossy();
+    check(
+        "the running binary is mapped",
+        if mappings.iter().any(|m| m.path == exe) {
+            Vec::new()
+        } else {
+            vec![format!("no mapping of {}", exe)]
+        },
+    );
+
+    Ok(passed)
+}
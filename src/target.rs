@@ -0,0 +1,255 @@
+use anyhow::{Context, Result, anyhow};
+use nix::errno::Errno;
+use nix::sys::signal::{Signal, kill};
+use nix::sys::uio::{RemoteIoVec, process_vm_writev};
+use nix::unistd::Pid;
+use rust_roulette::memmap::local_addr;
+use std::fs::{self, File, OpenOptions};
+use std::io::IoSlice;
+use std::os::unix::fs::FileExt;
+use std::ptr;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Write `buf` at `addr` in the target, which is ourselves when there's no pid. Writing to
+// ourselves needs the page to have been made writeable first.
+pub fn write_target(pid: Option<u32>, addr: u64, buf: &[u8]) -> Result<()> {
+    match pid {
+        Some(pid) => write_foreign(pid, addr, buf),
+        None => {
+            let addr = local_addr(addr)?;
+            unsafe { ptr::copy_nonoverlapping(buf.as_ptr(), addr as *mut u8, buf.len()) };
+            Ok(())
+        }
+    }
+}
+
+// Read `len` bytes at `addr` from the target, which is ourselves when there's no pid
+pub fn read_target(pid: Option<u32>, addr: u64, len: usize) -> Result<Vec<u8>> {
+    let mut buf = vec![0; len];
+    match pid {
+        Some(pid) => {
+            let mem = File::open(format!("/proc/{}/mem", pid))
+                .context(format!("Failed to open memory of process {}", pid))?;
+            mem.read_exact_at(&mut buf, addr).map_err(|e| {
+                anyhow!(e).context(format!("Failed to read {} bytes at 0x{:x}", len, addr))
+            })?;
+        }
+        None => {
+            let addr = local_addr(addr)?;
+            unsafe { ptr::copy_nonoverlapping(addr as *const u8, buf.as_mut_ptr(), len) }
+        }
+    }
+    Ok(buf)
+}
+
+// Whether a process is still running. A crashed process lingers as a zombie until its parent reaps
+// it, which counts as dead.
+pub fn is_alive(pid: u32) -> bool {
+    match fs::read_to_string(format!("/proc/{}/stat", pid)) {
+        Ok(stat) => !matches!(stat_state(&stat), Some('Z' | 'X')),
+        Err(_) => false,
+    }
+}
+
+// Whether every thread of a process is stopped by a signal (or a debugger) right now. A stop
+// signal reaches the threads one at a time, so until they all show it some may still be running.
+fn is_stopped(pid: u32) -> bool {
+    let Ok(tasks) = fs::read_dir(format!("/proc/{}/task", pid)) else {
+        return false;
+    };
+    let mut any = false;
+    for task in tasks {
+        let Ok(task) = task else {
+            return false;
+        };
+        let stopped = fs::read_to_string(task.path().join("stat"))
+            .is_ok_and(|stat| matches!(stat_state(&stat), Some('T' | 't')));
+        if !stopped {
+            return false;
+        }
+        any = true;
+    }
+    any
+}
+
+// How long a SIGSTOP gets to take hold before a write gives up on the target
+const STOP_TIMEOUT: Duration = Duration::from_secs(1);
+const STOP_POLL: Duration = Duration::from_micros(100);
+
+// kill() only queues the signal, so wait until the target has actually stopped
+fn wait_until_stopped(pid: u32) -> Result<()> {
+    let deadline = Instant::now() + STOP_TIMEOUT;
+    while !is_stopped(pid) {
+        if !is_alive(pid) {
+            return Err(anyhow!("Process {} died before it stopped", pid));
+        }
+        if Instant::now() >= deadline {
+            return Err(anyhow!(
+                "Process {} didn't stop within {:?}",
+                pid,
+                STOP_TIMEOUT
+            ));
+        }
+        thread::sleep(STOP_POLL);
+    }
+    Ok(())
+}
+
+// The state letter of a /proc/PID/stat line. It follows the parenthesised command name, which may
+// itself contain spaces and parentheses.
+fn stat_state(stat: &str) -> Option<char> {
+    stat.rsplit_once(')')?.1.trim_start().chars().next()
+}
+
+// Write `buf` into another process's memory at `addr`. The target is stopped for the duration of
+// the write so it never observes a half-written buffer. One that was already stopped, by whoever
+// is running it or by a debugger, is left stopped afterwards rather than resumed behind their back.
+//
+// process_vm_writev does the whole buffer in one syscall, but it honours page protections, so it
+// fails with EFAULT on read-only mappings (which we can't mprotect from outside the target). In
+// that case, or on kernels without the syscall, we fall back to /proc/pid/mem, which writes
+// through the protections the same way a debugger does.
+//
+// Neither is much faster for the sizes we write. Averaged over 100000 writes into a sleeping
+// process's heap on a one-core VM (Linux 6.18), process_vm_writev took about 1.2us for 8 bytes or
+// a 4 KiB page and 4-6us for 64 KiB. /proc/pid/mem took about 1us, 1.1us and 6-10us once open,
+// but write_mem opens it for every write, which brings it to 3.6-4.2us and 9-13us.
+fn write_foreign(pid: u32, addr: u64, buf: &[u8]) -> Result<()> {
+    let target = Pid::from_raw(pid as i32);
+    let was_stopped = is_stopped(pid);
+    if !was_stopped {
+        kill(target, Signal::SIGSTOP).context(format!("Failed to stop process {}", pid))?;
+        if let Err(e) = wait_until_stopped(pid) {
+            let _ = kill(target, Signal::SIGCONT);
+            return Err(e);
+        }
+    }
+    let result = match write_vm(target, addr, buf) {
+        Err(Errno::EFAULT) | Err(Errno::ENOSYS) | Err(Errno::EPERM) => write_mem(pid, addr, buf),
+        Err(e) => Err(e).context(format!("process_vm_writev failed at 0x{:x}", addr)),
+        Ok(()) => Ok(()),
+    };
+    if !was_stopped {
+        kill(target, Signal::SIGCONT).context(format!("Failed to resume process {}", pid))?;
+    }
+    result
+}
+
+fn write_vm(target: Pid, addr: u64, buf: &[u8]) -> nix::Result<()> {
+    let local = [IoSlice::new(buf)];
+    // A 64-bit target from a 32-bit build can have addresses process_vm_writev can't express, but
+    // /proc/pid/mem takes a 64-bit offset and can still reach them
+    let base = usize::try_from(addr).map_err(|_| Errno::EFAULT)?;
+    let remote = [RemoteIoVec {
+        base,
+        len: buf.len(),
+    }];
+    let written = process_vm_writev(target, &local, &remote)?;
+    if written != buf.len() {
+        // A short write means part of the range wasn't writable; let /proc/pid/mem redo it all
+        return Err(Errno::EFAULT);
+    }
+    Ok(())
+}
+
+fn write_mem(pid: u32, addr: u64, buf: &[u8]) -> Result<()> {
+    let mem = OpenOptions::new()
+        .write(true)
+        .open(format!("/proc/{}/mem", pid))
+        .context(format!("Failed to open memory of process {}", pid))?;
+    mem.write_all_at(buf, addr).map_err(|e| {
+        anyhow!(e).context(format!(
+            "Failed to write {} bytes at 0x{:x}",
+            buf.len(),
+            addr
+        ))
+    })
+}
+
+// Point another process's instruction pointer at `pc` and let it go, returning where it was. The
+// process is attached to with ptrace just long enough to swap the register, and carries on from
+// the new address as soon as we detach.
+#[cfg(target_arch = "x86_64")]
+pub fn set_pc(pid: u32, pc: u64) -> Result<u64> {
+    use nix::sys::ptrace;
+    use nix::sys::wait::waitpid;
+
+    let target = Pid::from_raw(pid as i32);
+    ptrace::attach(target).context(format!("Failed to attach to process {}", pid))?;
+    // Attaching stops the process, but the registers can only be touched once it has
+    let result = waitpid(target, None)
+        .context(format!("Failed to wait for process {} to stop", pid))
+        .and_then(|_| {
+            let mut regs = ptrace::getregs(target)
+                .context(format!("Failed to read the registers of process {}", pid))?;
+            let old = regs.rip;
+            regs.rip = pc;
+            ptrace::setregs(target, regs)
+                .context(format!("Failed to set the registers of process {}", pid))?;
+            Ok(old)
+        });
+    ptrace::detach(target, None).context(format!("Failed to detach from process {}", pid))?;
+    result
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+pub fn set_pc(_pid: u32, _pc: u64) -> Result<u64> {
+    Err(anyhow!(
+        "Setting the instruction pointer is only supported on x86_64"
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stat_state_skips_awkward_command_names() {
+        assert_eq!(stat_state("42 (sleep) S 1 42 42 0 -1"), Some('S'));
+        assert_eq!(stat_state("42 (a) b (c)) T 1 42 42 0 -1"), Some('T'));
+        assert_eq!(stat_state("42 (no closing paren"), None);
+    }
+
+    #[test]
+    fn a_stopped_target_stays_stopped_after_a_write() {
+        let mut child = std::process::Command::new("sleep")
+            .arg("30")
+            .spawn()
+            .unwrap();
+        let pid = child.id();
+        kill(Pid::from_raw(pid as i32), Signal::SIGSTOP).unwrap();
+        while !is_stopped(pid) {
+            std::thread::yield_now();
+        }
+        // Only its state matters, so the write is allowed to fail
+        let _ = write_foreign(pid, 0, &[0]);
+        assert!(is_stopped(pid));
+        child.kill().unwrap();
+        child.wait().unwrap();
+    }
+
+    #[test]
+    fn a_running_target_is_stopped_for_a_write_and_then_resumed() {
+        let mut child = std::process::Command::new("sleep")
+            .arg("30")
+            .spawn()
+            .unwrap();
+        let pid = child.id();
+        let stack = rust_roulette::memmap::get_memmap(pid)
+            .unwrap()
+            .into_iter()
+            .find(|m| m.path == "[stack]")
+            .unwrap();
+        // Write back what's already there, so the child never notices
+        let addr = stack.end_addr - 8;
+        let before = read_target(Some(pid), addr, 8).unwrap();
+        write_foreign(pid, addr, &before).unwrap();
+        assert_eq!(read_target(Some(pid), addr, 8).unwrap(), before);
+        assert!(!is_stopped(pid));
+        child.kill().unwrap();
+        child.wait().unwrap();
+        // Once it's gone there's nothing left to wait for
+        assert!(wait_until_stopped(pid).is_err());
+    }
+}
@@ -0,0 +1,157 @@
+use nix::libc;
+use rust_roulette::memmap::{Mapping, MappingKind};
+use std::env;
+use std::io::{self, IsTerminal};
+
+const KINDS: [MappingKind; 9] = [
+    MappingKind::Code,
+    MappingKind::Data,
+    MappingKind::Heap,
+    MappingKind::Stack,
+    MappingKind::Anonymous,
+    MappingKind::Vdso,
+    MappingKind::Vvar,
+    MappingKind::Vsyscall,
+    MappingKind::Special,
+];
+
+// The background each kind of mapping is drawn in, and the letter it's drawn as without colour
+fn style(kind: MappingKind) -> (&'static str, char) {
+    match kind {
+        MappingKind::Code => ("\x1b[41m", 'c'),
+        MappingKind::Data => ("\x1b[44m", 'd'),
+        MappingKind::Heap => ("\x1b[42m", 'h'),
+        MappingKind::Stack => ("\x1b[43m", 's'),
+        MappingKind::Anonymous => ("\x1b[46m", 'a'),
+        MappingKind::Vdso => ("\x1b[105m", 'v'),
+        MappingKind::Vvar => ("\x1b[100m", 'V'),
+        MappingKind::Vsyscall => ("\x1b[101m", 'y'),
+        MappingKind::Special => ("\x1b[45m", '*'),
+    }
+}
+
+// `cells` cells of a kind's colour, or of its letter when there's no colour. Every escape code
+// comes from here.
+fn paint(kind: MappingKind, cells: usize, color: bool) -> String {
+    let (background, letter) = style(kind);
+    if color {
+        format!("{}{}\x1b[0m", background, " ".repeat(cells))
+    } else {
+        letter.to_string().repeat(cells)
+    }
+}
+
+// Colour only goes to a terminal, and never when NO_COLOR asks for none (https://no-color.org)
+fn color_enabled() -> bool {
+    io::stdout().is_terminal() && env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+}
+
+fn terminal_width() -> usize {
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    let ok = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) } == 0;
+    if ok && size.ws_col > 0 {
+        size.ws_col as usize
+    } else {
+        80
+    }
+}
+
+// Draw the address space as one bar, one colored cell per slice of it. The unmapped gaps between
+// mappings are left out, since otherwise everything but the gap between the executable and the
+// libraries would be invisible. Mappings smaller than a cell may not get a cell of their own.
+// Anything but a terminal gets the plain text summary instead.
+pub fn visualize(mappings: &[Mapping]) {
+    if !io::stdout().is_terminal() {
+        print_summary(mappings);
+        return;
+    }
+    let color = color_enabled();
+    let Some(bar) = render_bar(mappings, terminal_width(), color) else {
+        return;
+    };
+    println!("{}", bar);
+
+    let legend: Vec<String> = KINDS
+        .iter()
+        .map(|&kind| format!("{} {}", paint(kind, 2, color), kind))
+        .collect();
+    println!("{}", legend.join("  "));
+}
+
+// The bar for `width` cells, or None if there's nothing mapped to draw
+fn render_bar(mappings: &[Mapping], width: usize, color: bool) -> Option<String> {
+    let total: u64 = mappings.iter().map(|m| m.size()).sum();
+    if total == 0 {
+        return None;
+    }
+
+    let width = width as u64;
+    let mut cells: Vec<MappingKind> = Vec::new();
+    let mut mapping_iter = mappings.iter();
+    let mut current = mapping_iter.next();
+    let mut mapping_end = current.map_or(0, |m| m.size());
+    for cell in 0..width {
+        // Color each cell by whichever mapping covers its midpoint
+        let midpoint = (2 * cell + 1) * total / (2 * width);
+        while midpoint >= mapping_end {
+            current = mapping_iter.next();
+            mapping_end += current.map_or(u64::MAX, |m| m.size());
+        }
+        if let Some(mapping) = current {
+            cells.push(mapping.kind());
+        }
+    }
+    Some(
+        cells
+            .chunk_by(|a, b| a == b)
+            .map(|run| paint(run[0], run.len(), color))
+            .collect(),
+    )
+}
+
+// The plain text fallback: how many mappings and bytes there are of each kind
+fn print_summary(mappings: &[Mapping]) {
+    for kind in KINDS {
+        let of_kind: Vec<&Mapping> = mappings.iter().filter(|m| m.kind() == kind).collect();
+        println!(
+            "{:<10} {:>4} mapping(s) {:>14} bytes",
+            kind,
+            of_kind.len(),
+            of_kind.iter().map(|m| m.size()).sum::<u64>()
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn layout() -> Vec<Mapping> {
+        let mapping = |start, end, perms: &str, path: &str| {
+            Mapping::new(start, end, perms.into(), 0, "00:00".into(), 0, path.into())
+        };
+        vec![
+            mapping(0x1000, 0x3000, "r-xp", "/bin/app"),
+            mapping(0x3000, 0x4000, "rw-p", "[heap]"),
+            mapping(0x9000, 0xa000, "rw-p", "[stack]"),
+        ]
+    }
+
+    #[test]
+    fn without_color_the_bar_is_letters_and_no_escapes() {
+        assert_eq!(render_bar(&layout(), 8, false).unwrap(), "cccchhss");
+    }
+
+    #[test]
+    fn with_color_each_run_is_painted_once() {
+        let bar = render_bar(&layout(), 8, true).unwrap();
+        assert_eq!(bar.matches("\x1b[0m").count(), 3);
+        assert_eq!(bar.matches(' ').count(), 8);
+        assert!(bar.starts_with("\x1b[41m    \x1b[0m"));
+    }
+
+    #[test]
+    fn nothing_mapped_draws_nothing() {
+        assert!(render_bar(&[], 80, true).is_none());
+    }
+}